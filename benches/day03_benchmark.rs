@@ -3,6 +3,16 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 const INPUT: &str = include_str!("../inputs/day03.txt");
 
+/// About 4 MB of memory made almost entirely of instructions.
+fn dense_input() -> String {
+    ["mul(12,34)", "do()", "mul(1,999)", "don't()", "mul(7,8)"]
+        .iter()
+        .cycle()
+        .take(500_000)
+        .copied()
+        .collect()
+}
+
 pub fn day03_part1_benchmark(c: &mut Criterion) {
     c.bench_function("day 03 part 1", |b| b.iter(|| part1(black_box(INPUT))));
 }
@@ -11,5 +21,20 @@ pub fn day03_part2_benchmark(c: &mut Criterion) {
     c.bench_function("day 03 part 2", |b| b.iter(|| part2(black_box(INPUT))));
 }
 
-criterion_group!(benches, day03_part1_benchmark, day03_part2_benchmark);
+pub fn day03_dense_benchmark(c: &mut Criterion) {
+    let input = dense_input();
+    c.bench_function("day 03 part 1 dense", |b| {
+        b.iter(|| part1(black_box(&input)))
+    });
+    c.bench_function("day 03 part 2 dense", |b| {
+        b.iter(|| part2(black_box(&input)))
+    });
+}
+
+criterion_group!(
+    benches,
+    day03_part1_benchmark,
+    day03_part2_benchmark,
+    day03_dense_benchmark
+);
 criterion_main!(benches);
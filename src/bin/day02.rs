@@ -1,7 +1,9 @@
 use advent_of_code_2024::day02::{part1, part2};
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let input = include_str!("../../inputs/day02.txt");
-    println!("Day 02 part 1: {}", part1(input));
-    println!("Day 02 part 2: {}", part2(input));
+    println!("Day 02 part 1: {}", part1(input)?);
+    println!("Day 02 part 2: {}", part2(input)?);
+
+    Ok(())
 }
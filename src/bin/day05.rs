@@ -1,9 +1,17 @@
-use advent_of_code_2024::day05::{part1, part2};
+use advent_of_code_2024::day05::{part1, part2, report};
 
 fn main() -> anyhow::Result<()> {
     let input = include_str!("../../inputs/day05.txt");
     println!("Day 05 part 1: {}", part1(input)?);
     println!("Day 05 part 2: {}", part2(input)?);
 
+    if std::env::args().any(|arg| arg == "--violations") {
+        for update_report in report(input)? {
+            if !update_report.violations.is_empty() {
+                print!("{update_report}");
+            }
+        }
+    }
+
     Ok(())
 }
@@ -1,7 +1,9 @@
 use advent_of_code_2024::day09::{part1, part2};
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let input = include_str!("../../inputs/day09.txt");
-    println!("Day 09 part 1: {}", part1(input));
-    println!("Day 09 part 2: {}", part2(input));
+    println!("Day 09 part 1: {}", part1(input)?);
+    println!("Day 09 part 2: {}", part2(input)?);
+
+    Ok(())
 }
@@ -1,74 +1,121 @@
+use anyhow::anyhow;
+
 type Report = Vec<i64>;
 
-pub fn part1(input: &str) -> i64 {
-    let mut reports: Vec<Report> = vec![];
+pub fn part1(input: &str) -> anyhow::Result<usize> {
+    let reports = parse(input)?;
+    Ok(reports.iter().filter(|report| is_safe(report)).count())
+}
 
-    for line in input.lines() {
-        reports.push(
-            line.split_ascii_whitespace()
-                .map(|level| level.parse::<i64>().unwrap())
-                .collect(),
-        );
-    }
+pub fn part2(input: &str) -> anyhow::Result<usize> {
+    let reports = parse(input)?;
+    Ok(reports
+        .iter()
+        .filter(|report| is_safe_with_dampener(report))
+        .count())
+}
 
-    reports.iter().filter(|report| is_safe(report)).count() as i64
+/// Classifies every report in the input, in input order.
+pub fn diagnose(input: &str) -> anyhow::Result<Vec<Diagnosis>> {
+    let reports = parse(input)?;
+    Ok(reports
+        .iter()
+        .map(|report| diagnose_report(report))
+        .collect())
 }
 
-pub fn part2(input: &str) -> i64 {
-    let mut reports: Vec<Report> = vec![];
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Diagnosis {
+    Safe,
+    SafeWithDampener {
+        removed_index: usize,
+    },
+    Unsafe {
+        reason: UnsafeReason,
+        levels: [usize; 2],
+    },
+}
 
-    for line in input.lines() {
-        reports.push(
-            line.split_ascii_whitespace()
-                .map(|level| level.parse::<i64>().unwrap())
-                .collect(),
-        );
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsafeReason {
+    DirectionChange,
+    StepTooSmall,
+    StepTooLarge,
+}
 
-    reports
-        .iter()
-        .filter(|report| is_safe_with_dampener(report))
-        .count() as i64
+fn diagnose_report(report: &[i64]) -> Diagnosis {
+    let Some((reason, levels)) = find_violation(report) else {
+        return Diagnosis::Safe;
+    };
+
+    match dampened_index(report) {
+        Some(removed_index) => Diagnosis::SafeWithDampener { removed_index },
+        None => Diagnosis::Unsafe { reason, levels },
+    }
 }
 
-fn is_safe(report: &Report) -> bool {
-    let decreasing = report[0] > report[1];
+fn find_violation(report: &[i64]) -> Option<(UnsafeReason, [usize; 2])> {
+    let decreasing = report.len() > 1 && report[0] > report[1];
 
-    for pair in report.windows(2) {
-        let difference = pair[0] - pair[1];
-        let magnitude = difference.abs();
+    for (index, pair) in report.windows(2).enumerate() {
+        let levels = [index, index + 1];
 
-        if decreasing != (difference > 0) {
-            return false;
+        match pair[0].abs_diff(pair[1]) {
+            0 => return Some((UnsafeReason::StepTooSmall, levels)),
+            4.. => return Some((UnsafeReason::StepTooLarge, levels)),
+            _ => {}
         }
 
-        if !(1..=3).contains(&magnitude) {
-            return false;
+        if decreasing != (pair[0] > pair[1]) {
+            return Some((UnsafeReason::DirectionChange, levels));
         }
     }
 
-    true
+    None
 }
 
-fn is_safe_with_dampener(report: &Report) -> bool {
-    if is_safe(report) {
-        return true;
-    }
+fn is_safe(report: &[i64]) -> bool {
+    find_violation(report).is_none()
+}
 
-    for (index, _) in report.iter().enumerate() {
-        let mut dampened = report.clone();
-        dampened.remove(index);
-        if is_safe(&dampened) {
-            return true;
-        }
-    }
+fn is_safe_with_dampener(report: &[i64]) -> bool {
+    is_safe(report) || dampened_index(report).is_some()
+}
 
-    false
+fn dampened_index(report: &[i64]) -> Option<usize> {
+    (0..report.len()).find(|index| {
+        let mut dampened = report.to_vec();
+        dampened.remove(*index);
+        is_safe(&dampened)
+    })
+}
+
+fn parse(input: &str) -> anyhow::Result<Vec<Report>> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(line_number, line)| {
+            let report = line
+                .split_ascii_whitespace()
+                .map(|level| {
+                    level.parse::<i64>().map_err(|e| {
+                        anyhow!("Invalid level {level:?} on line {}: {e}", line_number + 1)
+                    })
+                })
+                .collect::<anyhow::Result<Report>>()?;
+
+            if report.is_empty() {
+                return Err(anyhow!("Report on line {} has no levels", line_number + 1));
+            }
+
+            Ok(report)
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{diagnose, part1, part2, Diagnosis, UnsafeReason};
 
     const INPUT: &str = "7 6 4 2 1
 1 2 7 8 9
@@ -78,12 +125,57 @@ mod tests {
 1 3 6 7 9";
 
     #[test]
-    fn part1_returns_num_safe_levels() {
-        assert_eq!(part1(INPUT), 2);
+    fn part1_returns_num_safe_levels() -> anyhow::Result<()> {
+        assert_eq!(part1(INPUT)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn part2_returns_num_safe_levels_with_dampener() -> anyhow::Result<()> {
+        assert_eq!(part2(INPUT)?, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn diagnose_classifies_reports() -> anyhow::Result<()> {
+        assert_eq!(
+            diagnose(INPUT)?,
+            vec![
+                Diagnosis::Safe,
+                Diagnosis::Unsafe {
+                    reason: UnsafeReason::StepTooLarge,
+                    levels: [1, 2]
+                },
+                Diagnosis::Unsafe {
+                    reason: UnsafeReason::StepTooLarge,
+                    levels: [2, 3]
+                },
+                Diagnosis::SafeWithDampener { removed_index: 1 },
+                Diagnosis::SafeWithDampener { removed_index: 2 },
+                Diagnosis::Safe,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn single_level_reports_are_safe() -> anyhow::Result<()> {
+        assert_eq!(part1("5\n1 2")?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn extreme_levels_do_not_overflow() -> anyhow::Result<()> {
+        assert_eq!(
+            part1("9223372036854775807 -1\n-9223372036854775808 9223372036854775807")?,
+            0
+        );
+        Ok(())
     }
 
     #[test]
-    fn part2_returns_num_safe_levels_with_dampener() {
-        assert_eq!(part2(INPUT), 4);
+    fn malformed_reports_are_rejected() {
+        assert!(part1("1 2 x").is_err());
+        assert!(part2("1 2\n\n3 4").is_err());
     }
 }
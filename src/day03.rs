@@ -1,100 +1,460 @@
+use std::io::{ErrorKind, Read};
+
 use anyhow::anyhow;
-use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::character;
-use nom::character::complete::anychar;
-use nom::combinator::{map, value};
-use nom::multi::{many1, many_till};
-use nom::sequence::{delimited, separated_pair};
-use nom::IResult;
 
-#[derive(Clone)]
-enum Instruction {
-    Mul { multiplicand: i64, multiplier: i64 },
-    Do,
-    Dont,
+pub fn part1(input: &str) -> anyhow::Result<i64> {
+    let instruction_set = InstructionSet::new().instruction("mul", 2, Effect::Evaluate(product));
+    Ok(Interpreter::new(instruction_set).run(input)?.total)
 }
 
-fn mul_parser(input: &str) -> IResult<&str, Instruction> {
-    map(
-        delimited(
-            tag("mul("),
-            separated_pair(
-                character::complete::i64,
-                character::complete::char(','),
-                character::complete::i64,
-            ),
-            character::complete::char(')'),
-        ),
-        |(multiplicand, multiplier)| Instruction::Mul {
-            multiplicand,
-            multiplier,
-        },
-    )(input)
+pub fn part2(input: &str) -> anyhow::Result<i64> {
+    Ok(Interpreter::new(InstructionSet::standard())
+        .run(input)?
+        .total)
 }
 
-fn do_parser(input: &str) -> IResult<&str, Instruction> {
-    value(Instruction::Do, tag("do()"))(input)
+/// What executing a recognized instruction does to the interpreter.
+#[derive(Clone, Copy)]
+pub enum Effect {
+    /// Adds the result to the total while evaluation is enabled. The function returns `None` if
+    /// the result overflows.
+    Evaluate(fn(&[i64]) -> Option<i64>),
+    Enable,
+    Disable,
 }
 
-fn dont_parser(input: &str) -> IResult<&str, Instruction> {
-    value(Instruction::Dont, tag("don't()"))(input)
+#[derive(Clone)]
+struct InstructionDefinition {
+    name: &'static str,
+    arity: usize,
+    effect: Effect,
 }
 
-fn instruction_parser(input: &str) -> IResult<&str, Instruction> {
-    alt((mul_parser, do_parser, dont_parser))(input)
+/// The table of instructions an [`Interpreter`] recognizes in corrupted memory.
+#[derive(Clone, Default)]
+pub struct InstructionSet {
+    definitions: Vec<InstructionDefinition>,
 }
 
-fn memory_parser(input: &str) -> IResult<&str, Vec<Instruction>> {
-    many1(map(
-        many_till(anychar, instruction_parser),
-        |(_, instructions)| instructions,
-    ))(input)
+impl InstructionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The puzzle's instructions: `mul(a,b)`, `do()` and `don't()`.
+    pub fn standard() -> Self {
+        Self::new()
+            .instruction("mul", 2, Effect::Evaluate(product))
+            .instruction("do", 0, Effect::Enable)
+            .instruction("don't", 0, Effect::Disable)
+    }
+
+    /// Registers an instruction written as `name(operand,...)` with exactly `arity` operands.
+    pub fn instruction(mut self, name: &'static str, arity: usize, effect: Effect) -> Self {
+        self.definitions.push(InstructionDefinition {
+            name,
+            arity,
+            effect,
+        });
+        self
+    }
 }
 
-pub fn part1(input: &str) -> anyhow::Result<i64> {
-    let (_, instructions) =
-        memory_parser(input).map_err(|e| anyhow!("Failed to parse memory: {}", e))?;
-    let solution = instructions
+pub fn product(operands: &[i64]) -> Option<i64> {
+    operands
         .iter()
-        .map(|instruction| match instruction {
-            Instruction::Mul {
-                multiplicand,
-                multiplier,
-            } => multiplicand * multiplier,
-            _ => 0,
-        })
-        .sum();
-    Ok(solution)
+        .try_fold(1i64, |product, operand| product.checked_mul(*operand))
 }
 
-pub fn part2(input: &str) -> anyhow::Result<i64> {
-    let (_, instructions) =
-        memory_parser(input).map_err(|e| anyhow!("Failed to parse memory: {}", e))?;
-
-    let mut solution = 0;
-    let mut muls_enabled = true;
-    for instruction in instructions {
-        match instruction {
-            Instruction::Mul {
-                multiplicand,
-                multiplier,
-            } => {
-                if muls_enabled {
-                    solution += multiplicand * multiplier
+pub fn sum(operands: &[i64]) -> Option<i64> {
+    operands
+        .iter()
+        .try_fold(0i64, |sum, operand| sum.checked_add(*operand))
+}
+
+pub fn difference(operands: &[i64]) -> Option<i64> {
+    match operands.split_first() {
+        Some((first, rest)) => first.checked_sub(sum(rest)?),
+        None => Some(0),
+    }
+}
+
+pub struct Interpreter {
+    instruction_set: InstructionSet,
+    record_trace: bool,
+    report_near_misses: bool,
+    anchors: [bool; 256],
+}
+
+impl Interpreter {
+    pub fn new(instruction_set: InstructionSet) -> Self {
+        let mut anchors = [false; 256];
+        for definition in &instruction_set.definitions {
+            if let Some(first) = definition.name.bytes().next() {
+                anchors[first as usize] = true;
+            }
+        }
+
+        Interpreter {
+            instruction_set,
+            record_trace: false,
+            report_near_misses: false,
+            anchors,
+        }
+    }
+
+    /// Also records every recognized instruction in [`Execution::trace`].
+    pub fn with_trace(mut self) -> Self {
+        self.record_trace = true;
+        self
+    }
+
+    /// Also records fragments that look like instructions but fail to parse.
+    pub fn with_near_misses(mut self) -> Self {
+        self.report_near_misses = true;
+        self
+    }
+
+    pub fn run(&self, memory: &str) -> anyhow::Result<Execution> {
+        let mut state = ExecutionState::new(self.record_trace);
+        self.scan(&mut state, memory.as_bytes(), 0, true)?;
+        Ok(state.execution)
+    }
+
+    /// Runs over memory read incrementally, without holding the whole stream in memory.
+    pub fn run_reader(&self, mut reader: impl Read) -> anyhow::Result<Execution> {
+        let mut state = ExecutionState::new(self.record_trace);
+        let mut buffer = Vec::new();
+        let mut chunk = vec![0; READ_CHUNK_LEN];
+        let mut base = 0;
+
+        loop {
+            let len = match reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            buffer.extend_from_slice(&chunk[..len]);
+
+            let complete = len == 0;
+            let consumed = self.scan(&mut state, &buffer, base, complete)?;
+            if complete {
+                break;
+            }
+            buffer.drain(..consumed);
+            base += consumed;
+        }
+
+        Ok(state.execution)
+    }
+
+    /// Executes every instruction in `memory`, which starts at byte `base` of the stream, and
+    /// returns how many bytes were consumed. Unless `complete`, stops early at a candidate
+    /// instruction that runs off the end of `memory`.
+    fn scan(
+        &self,
+        state: &mut ExecutionState,
+        memory: &[u8],
+        base: usize,
+        complete: bool,
+    ) -> anyhow::Result<usize> {
+        let mut position = 0;
+        let mut operands = Vec::new();
+
+        while let Some(skipped) = memory[position..]
+            .iter()
+            .position(|byte| self.anchors[*byte as usize])
+        {
+            position += skipped;
+            match self.parse_at(memory, position, complete, &mut operands) {
+                Candidate::Instruction { definition, end } => {
+                    state.execute(definition, &operands, base + position)?;
+                    position = end;
                 }
+                Candidate::NearMiss(near_miss) => {
+                    state.execution.near_misses.push(NearMiss {
+                        offset: base + near_miss.offset,
+                        ..near_miss
+                    });
+                    position += 1;
+                }
+                Candidate::None => position += 1,
+                Candidate::Incomplete => return Ok(position),
             }
-            Instruction::Do => muls_enabled = true,
-            Instruction::Dont => muls_enabled = false,
         }
+
+        Ok(memory.len())
     }
 
-    Ok(solution)
+    /// Tries every instruction whose name starts at `start`. On failure, reports the near miss
+    /// of the longest matching name, if any name matched at all. The operands of a recognized
+    /// instruction are left in `operands`.
+    fn parse_at(
+        &self,
+        memory: &[u8],
+        start: usize,
+        complete: bool,
+        operands: &mut Vec<i64>,
+    ) -> Candidate<'_> {
+        let rest = &memory[start..];
+        let mut near_miss: Option<(usize, NearMissReason, usize)> = None;
+
+        for definition in &self.instruction_set.definitions {
+            let name = definition.name.as_bytes();
+            if !rest.starts_with(name) {
+                if !complete && name.starts_with(rest) {
+                    return Candidate::Incomplete;
+                }
+                continue;
+            }
+
+            let (reason, failure) =
+                match parse_operands(memory, start + name.len(), definition.arity, operands) {
+                    Ok(end) if operands.len() == definition.arity => {
+                        return Candidate::Instruction { definition, end };
+                    }
+                    Ok(end) => (
+                        NearMissReason::WrongArity {
+                            expected: definition.arity,
+                            found: operands.len(),
+                        },
+                        end - 1,
+                    ),
+                    Err((_, failure)) if failure == memory.len() && !complete => {
+                        return Candidate::Incomplete;
+                    }
+                    Err((reason, failure)) => (reason, failure),
+                };
+
+            if near_miss
+                .as_ref()
+                .is_none_or(|(len, _, _)| name.len() > *len)
+            {
+                near_miss = Some((name.len(), reason, failure));
+            }
+        }
+
+        match near_miss {
+            Some(_) if !self.report_near_misses => Candidate::None,
+            Some((_, _, failure))
+                if !complete && memory.len() < (failure + 4).max(start + MAX_FRAGMENT_LEN) =>
+            {
+                Candidate::Incomplete
+            }
+            Some((_, reason, failure)) => Candidate::NearMiss(NearMiss {
+                offset: start,
+                fragment: fragment(memory, start, failure, &reason),
+                reason,
+            }),
+            None => Candidate::None,
+        }
+    }
+}
+
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
+const MAX_OPERAND_DIGITS: usize = 3;
+
+enum Candidate<'a> {
+    Instruction {
+        definition: &'a InstructionDefinition,
+        end: usize,
+    },
+    NearMiss(NearMiss),
+    None,
+    Incomplete,
+}
+
+struct ExecutionState {
+    execution: Execution,
+    disabled: bool,
+    record_trace: bool,
+}
+
+impl ExecutionState {
+    fn new(record_trace: bool) -> Self {
+        ExecutionState {
+            execution: Execution::default(),
+            disabled: false,
+            record_trace,
+        }
+    }
+
+    fn execute(
+        &mut self,
+        definition: &InstructionDefinition,
+        operands: &[i64],
+        offset: usize,
+    ) -> anyhow::Result<()> {
+        let enabled = !self.disabled;
+        let value = match definition.effect {
+            Effect::Evaluate(evaluate) => {
+                let value = evaluate(operands).ok_or_else(|| {
+                    anyhow!("{} overflowed at byte offset {offset}", definition.name)
+                })?;
+                if enabled {
+                    self.execution.total = self
+                        .execution
+                        .total
+                        .checked_add(value)
+                        .ok_or_else(|| anyhow!("Total overflowed at byte offset {offset}"))?;
+                }
+                Some(value)
+            }
+            Effect::Enable => {
+                self.disabled = false;
+                None
+            }
+            Effect::Disable => {
+                self.disabled = true;
+                None
+            }
+        };
+
+        if self.record_trace {
+            self.execution.trace.push(TraceEntry {
+                offset,
+                name: definition.name,
+                operands: operands.to_vec(),
+                enabled,
+                value,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Parses `(operand,...)` starting at `position` into `operands`, reading at most one operand
+/// past `arity`. Returns the offset just past the closing parenthesis, or the reason and
+/// position of the first offending byte.
+fn parse_operands(
+    memory: &[u8],
+    position: usize,
+    arity: usize,
+    operands: &mut Vec<i64>,
+) -> Result<usize, (NearMissReason, usize)> {
+    let mut position = position;
+    match memory.get(position) {
+        Some(b'(') => position += 1,
+        _ => return Err((NearMissReason::MissingOpeningParenthesis, position)),
+    }
+
+    operands.clear();
+    if memory.get(position) == Some(&b')') {
+        return Ok(position + 1);
+    }
+
+    loop {
+        let digits = memory[position..]
+            .iter()
+            .take(MAX_OPERAND_DIGITS + 1)
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        if digits == 0 || digits > MAX_OPERAND_DIGITS {
+            return Err((
+                NearMissReason::InvalidOperand,
+                position + digits.min(MAX_OPERAND_DIGITS),
+            ));
+        }
+
+        operands.push(
+            memory[position..position + digits]
+                .iter()
+                .fold(0, |acc, digit| acc * 10 + i64::from(digit - b'0')),
+        );
+        position += digits;
+
+        match memory.get(position) {
+            Some(b',') if operands.len() > arity => return Ok(position + 1),
+            Some(b',') => position += 1,
+            Some(b')') => return Ok(position + 1),
+            _ => return Err((NearMissReason::MissingClosingParenthesis, position)),
+        }
+    }
+}
+
+const MAX_FRAGMENT_LEN: usize = 16;
+
+/// The text of a near miss, running through the character that broke the instruction. A
+/// bracket used in place of the opening parenthesis extends the fragment through its closer.
+fn fragment(memory: &[u8], start: usize, failure: usize, reason: &NearMissReason) -> String {
+    let mut end = (failure + 1).min(memory.len());
+    while end < memory.len() && memory[end] & 0b1100_0000 == 0b1000_0000 {
+        end += 1;
+    }
+
+    if *reason == NearMissReason::MissingOpeningParenthesis
+        && matches!(memory.get(failure), Some(b'[' | b'{' | b'<'))
+    {
+        if let Some(close) = memory[failure..]
+            .iter()
+            .take(MAX_FRAGMENT_LEN.saturating_sub(failure - start))
+            .take_while(|byte| !byte.is_ascii_whitespace())
+            .position(|byte| matches!(byte, b')' | b']' | b'}' | b'>'))
+        {
+            end = failure + close + 1;
+        }
+    }
+
+    String::from_utf8_lossy(&memory[start..end]).into_owned()
+}
+
+#[derive(Debug, Default)]
+pub struct Execution {
+    pub total: i64,
+    /// Empty unless the interpreter was built [`with_trace`](Interpreter::with_trace).
+    pub trace: Vec<TraceEntry>,
+    pub near_misses: Vec<NearMiss>,
+}
+
+/// A recognized instruction. `enabled` is whether evaluation was enabled when it was reached,
+/// and `value` is the evaluated result for [`Effect::Evaluate`] instructions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub offset: usize,
+    pub name: &'static str,
+    pub operands: Vec<i64>,
+    pub enabled: bool,
+    pub value: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NearMiss {
+    pub offset: usize,
+    pub fragment: String,
+    pub reason: NearMissReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NearMissReason {
+    MissingOpeningParenthesis,
+    InvalidOperand,
+    MissingClosingParenthesis,
+    /// Counting stops at one operand past `expected`.
+    WrongArity {
+        expected: usize,
+        found: usize,
+    },
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use std::io::Read;
+
+    use super::{
+        part1, part2, product, sum, Effect, InstructionSet, Interpreter, NearMiss, NearMissReason,
+    };
+
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(1);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
 
     #[test]
     fn part1_identifies_and_adds_valid_muls() -> anyhow::Result<()> {
@@ -109,4 +469,104 @@ mod tests {
         assert_eq!(part2(input)?, 48);
         Ok(())
     }
+
+    #[test]
+    fn trace_records_offsets_and_enablement() -> anyhow::Result<()> {
+        let input = "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+        let execution = Interpreter::new(InstructionSet::standard())
+            .with_trace()
+            .run(input)?;
+        let trace = execution
+            .trace
+            .iter()
+            .map(|entry| (entry.offset, entry.name, entry.enabled))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            trace,
+            vec![
+                (1, "mul", true),
+                (20, "don't", true),
+                (28, "mul", false),
+                (48, "mul", false),
+                (59, "do", false),
+                (64, "mul", true),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn near_misses_explain_skipped_fragments() -> anyhow::Result<()> {
+        let input = "xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+        let instruction_set = InstructionSet::new().instruction("mul", 2, Effect::Evaluate(sum));
+        let execution = Interpreter::new(instruction_set)
+            .with_near_misses()
+            .run(input)?;
+
+        assert_eq!(execution.total, 2 + 4 + 5 + 5 + 11 + 8 + 8 + 5);
+        assert_eq!(
+            execution.near_misses,
+            vec![
+                NearMiss {
+                    offset: 11,
+                    fragment: "mul[3,7]".to_string(),
+                    reason: NearMissReason::MissingOpeningParenthesis,
+                },
+                NearMiss {
+                    offset: 38,
+                    fragment: "mul(32,64]".to_string(),
+                    reason: NearMissReason::MissingClosingParenthesis,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn operands_are_limited_to_three_digits() -> anyhow::Result<()> {
+        let execution = Interpreter::new(InstructionSet::standard())
+            .with_near_misses()
+            .run("mul(123,4)mul(1234,5)mul(-2,3)")?;
+
+        assert_eq!(execution.total, 492);
+        assert_eq!(
+            execution
+                .near_misses
+                .iter()
+                .map(|near_miss| (near_miss.fragment.as_str(), near_miss.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                ("mul(1234", NearMissReason::InvalidOperand),
+                ("mul(-", NearMissReason::InvalidOperand),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn run_reader_matches_run() -> anyhow::Result<()> {
+        let input = "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+        let interpreter = Interpreter::new(InstructionSet::standard())
+            .with_trace()
+            .with_near_misses();
+        let expected = interpreter.run(input)?;
+        let streamed = interpreter.run_reader(Trickle(input.as_bytes()))?;
+
+        assert_eq!(streamed.total, expected.total);
+        assert_eq!(streamed.trace, expected.trace);
+        assert_eq!(expected.trace.len(), 6);
+        assert_eq!(streamed.near_misses, expected.near_misses);
+        Ok(())
+    }
+
+    #[test]
+    fn overflowing_instructions_are_rejected() {
+        let instruction_set =
+            InstructionSet::new().instruction("mul", 7, Effect::Evaluate(product));
+        let interpreter = Interpreter::new(instruction_set);
+
+        assert!(interpreter.run("mul(999,999,999,999,999,999,999)").is_err());
+        assert!(interpreter.run("mul(99,99,99,99,99,99,99)").is_ok());
+    }
 }
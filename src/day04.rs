@@ -1,4 +1,31 @@
-const XMAS_DIRECTIONS: [[isize; 2]; 8] = [
+use anyhow::anyhow;
+use ndarray::Array2;
+
+pub fn part1(input: &str) -> anyhow::Result<usize> {
+    let grid = WordGrid::parse(input)?;
+    Ok(grid.find_words(&["XMAS"], &ALL_DIRECTIONS, false).len())
+}
+
+pub fn part2(input: &str) -> anyhow::Result<usize> {
+    let grid = WordGrid::parse(input)?;
+    let x_mas = Stencil::parse("M.S\n.A.\nM.S", '.')?;
+    Ok(x_mas
+        .rotations()
+        .iter()
+        .map(|stencil| grid.find_stencil(stencil).len())
+        .sum())
+}
+
+pub type Position = (usize, usize);
+
+/// A `[row, column]` step between consecutive letters of a word.
+pub type Direction = [isize; 2];
+
+pub const ORTHOGONAL_DIRECTIONS: [Direction; 4] = [[0, 1], [0, -1], [1, 0], [-1, 0]];
+
+pub const DIAGONAL_DIRECTIONS: [Direction; 4] = [[1, 1], [1, -1], [-1, -1], [-1, 1]];
+
+pub const ALL_DIRECTIONS: [Direction; 8] = [
     [0, 1],
     [0, -1],
     [1, 0],
@@ -9,112 +36,183 @@ const XMAS_DIRECTIONS: [[isize; 2]; 8] = [
     [-1, 1],
 ];
 
-pub fn part1(input: &str) -> u32 {
-    let word = "XMAS";
-    let num_rows = input.lines().count();
-    let num_cols = input
-        .lines()
-        .next()
-        .map(|line| line.len())
-        .unwrap_or_default();
-
-    let mut count = 0;
-    for (row, line) in input.lines().enumerate() {
-        for (col, _letter) in line.char_indices() {
-            for [row_direction, col_direction] in XMAS_DIRECTIONS {
-                if matches_in_direction(
-                    input,
-                    word,
-                    row as isize,
-                    col as isize,
-                    row_direction,
-                    col_direction,
-                    num_rows,
-                    num_cols,
-                ) {
-                    count += 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordMatch {
+    pub word: String,
+    pub start: Position,
+    pub direction: Direction,
+}
+
+pub struct WordGrid {
+    letters: Array2<char>,
+}
+
+impl WordGrid {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        Ok(WordGrid {
+            letters: parse_grid(input)?,
+        })
+    }
+
+    /// Finds every occurrence of each word reading along any of `directions`. With
+    /// `wrap_around`, words continue on the opposite edge of the grid.
+    pub fn find_words(
+        &self,
+        words: &[&str],
+        directions: &[Direction],
+        wrap_around: bool,
+    ) -> Vec<WordMatch> {
+        let words = words
+            .iter()
+            .map(|word| (*word, word.chars().collect::<Vec<char>>()))
+            .collect::<Vec<_>>();
+
+        let mut matches = Vec::new();
+        for (start, _) in self.letters.indexed_iter() {
+            for (word, letters) in &words {
+                for direction in directions {
+                    if self.matches_in_direction(letters, start, direction, wrap_around) {
+                        matches.push(WordMatch {
+                            word: word.to_string(),
+                            start,
+                            direction: *direction,
+                        });
+                    }
                 }
             }
         }
-    }
 
-    count
-}
+        matches
+    }
 
-const X_MAS_DIRECTIONS: [[isize; 2]; 4] = [[1, 1], [1, -1], [-1, -1], [-1, 1]];
+    /// Returns the top-left position of every placement where the stencil matches.
+    pub fn find_stencil(&self, stencil: &Stencil) -> Vec<Position> {
+        let (num_rows, num_cols) = self.letters.dim();
+        let (stencil_rows, stencil_cols) = stencil.cells.dim();
+        if stencil_rows > num_rows || stencil_cols > num_cols {
+            return Vec::new();
+        }
 
-pub fn part2(input: &str) -> u32 {
-    let word = "MAS";
-    let num_rows = input.lines().count();
-    let num_cols = input
-        .lines()
-        .next()
-        .map(|line| line.len())
-        .unwrap_or_default();
-
-    let mut count = 0;
-    for (row, line) in input.lines().enumerate() {
-        for (col, _letter) in line.char_indices() {
-            let mut num_matches = 0;
-            for [row_direction, col_direction] in X_MAS_DIRECTIONS {
-                if matches_in_direction(
-                    input,
-                    word,
-                    row as isize - row_direction,
-                    col as isize - col_direction,
-                    row_direction,
-                    col_direction,
-                    num_rows,
-                    num_cols,
-                ) {
-                    num_matches += 1;
+        let mut positions = Vec::new();
+        for row in 0..=(num_rows - stencil_rows) {
+            for col in 0..=(num_cols - stencil_cols) {
+                let matches = stencil.cells.indexed_iter().all(|((i, j), cell)| {
+                    cell.is_none_or(|letter| self.letters[(row + i, col + j)] == letter)
+                });
+                if matches {
+                    positions.push((row, col));
                 }
             }
+        }
 
-            if num_matches == 2 {
-                count += 1;
+        positions
+    }
+
+    fn matches_in_direction(
+        &self,
+        word: &[char],
+        (row, col): Position,
+        [row_direction, col_direction]: &Direction,
+        wrap_around: bool,
+    ) -> bool {
+        let (num_rows, num_cols) = self.letters.dim();
+        let num_rows = num_rows as isize;
+        let num_cols = num_cols as isize;
+
+        for (index, letter) in word.iter().enumerate() {
+            let index = index as isize;
+            let mut next_row = row as isize + index * row_direction;
+            let mut next_col = col as isize + index * col_direction;
+
+            if wrap_around {
+                next_row = next_row.rem_euclid(num_rows);
+                next_col = next_col.rem_euclid(num_cols);
+            } else if next_row < 0 || next_row >= num_rows || next_col < 0 || next_col >= num_cols {
+                return false;
+            }
+
+            if self.letters[(next_row as usize, next_col as usize)] != *letter {
+                return false;
             }
         }
+
+        true
     }
-    count
 }
 
-fn matches_in_direction(
-    input: &str,
-    word: &str,
-    row: isize,
-    col: isize,
-    row_direction: isize,
-    col_direction: isize,
-    num_rows: usize,
-    num_cols: usize,
-) -> bool {
-    for (index, letter) in word.char_indices() {
-        let index = index as isize;
-        let next_row = row + index * row_direction;
-        let next_col = col + index * col_direction;
-
-        if next_row < 0
-            || next_row >= num_rows as isize
-            || next_col < 0
-            || next_col >= num_cols as isize
-        {
-            return false;
+/// A small 2D template of letters, where `None` matches any letter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stencil {
+    cells: Array2<Option<char>>,
+}
+
+impl Stencil {
+    /// Parses a template in which `wildcard` stands for any letter.
+    pub fn parse(template: &str, wildcard: char) -> anyhow::Result<Self> {
+        Ok(Stencil {
+            cells: parse_grid(template)?.mapv(|letter| (letter != wildcard).then_some(letter)),
+        })
+    }
+
+    /// Returns the stencil rotated a quarter turn clockwise.
+    pub fn rotate(&self) -> Self {
+        let (num_rows, num_cols) = self.cells.dim();
+        Stencil {
+            cells: Array2::from_shape_fn((num_cols, num_rows), |(row, col)| {
+                self.cells[(num_rows - 1 - col, row)]
+            }),
         }
+    }
 
-        let next_row = next_row as usize;
-        let next_col = next_col as usize;
-        if input.as_bytes()[next_row * (num_cols + 1) + next_col] != letter as u8 {
-            return false;
+    /// Returns the distinct quarter-turn rotations of the stencil, starting with itself.
+    pub fn rotations(&self) -> Vec<Self> {
+        let mut rotations = vec![self.clone()];
+        for _ in 0..3 {
+            let next = rotations[rotations.len() - 1].rotate();
+            if !rotations.contains(&next) {
+                rotations.push(next);
+            }
         }
+
+        rotations
     }
+}
 
-    true
+/// Parses rows of characters, ignoring `\r` line endings and trailing blank lines. Widths are
+/// counted in characters, so multi-byte letters occupy a single cell.
+fn parse_grid(input: &str) -> anyhow::Result<Array2<char>> {
+    let mut rows: Vec<Vec<char>> = input
+        .lines()
+        .map(|line| line.trim_end_matches('\r').chars().collect())
+        .collect();
+    while rows.last().is_some_and(|row| row.is_empty()) {
+        rows.pop();
+    }
+
+    let num_rows = rows.len();
+    let num_cols = rows.first().map_or(0, |row| row.len());
+    if let Some((index, row)) = rows
+        .iter()
+        .enumerate()
+        .find(|(_, row)| row.len() != num_cols)
+    {
+        return Err(anyhow!(
+            "Grid is not rectangular: row {} has {} characters, expected {}",
+            index + 1,
+            row.len(),
+            num_cols
+        ));
+    }
+
+    Array2::from_shape_vec((num_rows, num_cols), rows.into_iter().flatten().collect())
+        .map_err(|e| anyhow!("{}", e))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        part1, part2, Stencil, WordGrid, WordMatch, ALL_DIRECTIONS, ORTHOGONAL_DIRECTIONS,
+    };
 
     const INPUT: &str = "MMMSXXMASM
 MSAMXMSMSA
@@ -129,13 +227,68 @@ MXMXAXMASX";
 
     #[test]
     fn part1_counts_xmas() -> anyhow::Result<()> {
-        assert_eq!(part1(INPUT), 18);
+        assert_eq!(part1(INPUT)?, 18);
         Ok(())
     }
 
     #[test]
     fn part2_counts_x_mas() -> anyhow::Result<()> {
-        assert_eq!(part2(INPUT), 9);
+        assert_eq!(part2(INPUT)?, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn find_words_reports_positions_and_wraps_around() -> anyhow::Result<()> {
+        let grid = WordGrid::parse("ABC\nDEF\nGHI")?;
+
+        assert_eq!(
+            grid.find_words(&["CA", "EH"], &ORTHOGONAL_DIRECTIONS, false),
+            vec![WordMatch {
+                word: "EH".to_string(),
+                start: (1, 1),
+                direction: [1, 0],
+            }]
+        );
+        assert_eq!(
+            grid.find_words(&["CA"], &ORTHOGONAL_DIRECTIONS, true),
+            vec![WordMatch {
+                word: "CA".to_string(),
+                start: (0, 2),
+                direction: [0, 1],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_stencil_matches_plus_shapes() -> anyhow::Result<()> {
+        let grid = WordGrid::parse("XAXX\nAAAX\nXAXA\nXXAA")?;
+        let plus = Stencil::parse(".A.\nAAA\n.A.", '.')?;
+
+        assert_eq!(plus.rotations().len(), 1);
+        assert_eq!(grid.find_stencil(&plus), vec![(0, 0)]);
         Ok(())
     }
+
+    #[test]
+    fn parse_handles_multi_byte_letters_and_crlf() -> anyhow::Result<()> {
+        let grid = WordGrid::parse("ÄÖÜX\r\nXÖXX\r\nÜXÖX\r\n\r\n")?;
+
+        assert_eq!(
+            grid.find_words(&["ÄÖÜ"], &ALL_DIRECTIONS, false),
+            vec![WordMatch {
+                word: "ÄÖÜ".to_string(),
+                start: (0, 0),
+                direction: [0, 1],
+            }]
+        );
+        assert_eq!(grid.find_words(&["ÄÖÖ"], &ALL_DIRECTIONS, false).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_non_rectangular_grids() {
+        assert!(WordGrid::parse("ABC\nDE\nFGH").is_err());
+        assert!(part1("XMAS\nXMA").is_err());
+    }
 }
@@ -1,8 +1,10 @@
-use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
 use anyhow::anyhow;
+use itertools::Itertools;
 use nom::character;
 use nom::character::complete::line_ending;
 use nom::multi::fold_many1;
@@ -12,58 +14,408 @@ use nom::sequence::terminated;
 use nom::IResult;
 
 type Multimap<K, V> = HashMap<K, HashSet<V>>;
+type Update = Vec<u32>;
 
 pub fn part1(input: &str) -> anyhow::Result<u32> {
-    let (_, (page_ordering_rules, page_updates)) =
-        parse(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
+    let PrintQueue { rules, updates } = parse(input)?;
 
-    Ok(page_updates
+    Ok(updates
         .iter()
-        .filter(|update| {
-            update.is_sorted_by(|a, b| {
-                page_ordering_rules
-                    .get(a)
-                    .map_or(false, |followers| followers.contains(b))
-            })
-        })
+        .filter(|update| rules.is_ordered(update))
         .map(|update| update[update.len() / 2])
         .sum())
 }
 
 pub fn part2(input: &str) -> anyhow::Result<u32> {
-    let (_, (page_ordering_rules, mut page_updates)) =
-        parse(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
-
-    Ok(page_updates
-        .iter_mut()
-        .filter(|update| {
-            !update.is_sorted_by(|a, b| {
-                page_ordering_rules
-                    .get(a)
-                    .map_or(false, |followers| followers.contains(b))
-            })
+    let PrintQueue { rules, updates } = parse(input)?;
+
+    updates
+        .iter()
+        .filter(|update| !rules.is_ordered(update))
+        .map(|update| match rules.topological_order(update) {
+            TopologicalOrder::Unique(order) | TopologicalOrder::Ambiguous { order, .. } => {
+                Ok(order[order.len() / 2])
+            }
+            TopologicalOrder::Cycle(pages) => Err(anyhow!(
+                "Update {:?} cannot be ordered, its rules form a cycle through pages {:?}",
+                update,
+                pages
+            )),
         })
-        .map(|update| {
-            let pivot_position = update.len() / 2;
-            let (_lesser, pivot, _greater) =
-                update.select_nth_unstable_by(pivot_position, |a, b| {
-                    match page_ordering_rules.get(a) {
-                        Some(followers) => {
-                            if followers.contains(b) {
-                                Ordering::Less
-                            } else {
-                                Ordering::Greater
-                            }
-                        }
-                        None => Ordering::Greater,
-                    }
-                });
-            &*pivot
+        .sum()
+}
+
+pub struct PrintQueue {
+    pub rules: PageOrderingRules,
+    pub updates: Vec<Update>,
+}
+
+pub struct PageOrderingRules {
+    followers: Multimap<u32, u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TopologicalOrder {
+    Unique(Vec<u32>),
+    /// More than one order satisfies the rules. Unconstrained pages keep their relative order
+    /// from the update, and `interchangeable` lists the pages that could have come next at the
+    /// first point where the order had a choice.
+    Ambiguous {
+        order: Vec<u32>,
+        interchangeable: Vec<u32>,
+    },
+    /// The rules restricted to the update form a cycle, listed so that each page must precede
+    /// the next and the last must precede the first.
+    Cycle(Vec<u32>),
+}
+
+/// A rule `page|follower` broken because `page` sits at `positions[0]`, after `follower` at
+/// `positions[1]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub page: u32,
+    pub follower: u32,
+    pub positions: [usize; 2],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Correction {
+    AlreadyOrdered,
+    /// `order` is reached from the update by moving `moves` pages, the fewest possible.
+    Reordered {
+        moves: usize,
+        order: Vec<u32>,
+    },
+    Impossible {
+        cycle: Vec<u32>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub update: Vec<u32>,
+    pub violations: Vec<Violation>,
+    pub correction: Correction,
+}
+
+impl std::fmt::Display for UpdateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Update {}", self.update.iter().join(","))?;
+        for Violation {
+            page,
+            follower,
+            positions,
+        } in &self.violations
+        {
+            writeln!(
+                f,
+                "  violates {page}|{follower} (page {page} at position {}, page {follower} at position {})",
+                positions[0], positions[1]
+            )?;
+        }
+        match &self.correction {
+            Correction::AlreadyOrdered => writeln!(f, "  already ordered"),
+            Correction::Reordered { moves, order } => writeln!(
+                f,
+                "  fixed with {moves} move{}: {}",
+                if *moves == 1 { "" } else { "s" },
+                order.iter().join(",")
+            ),
+            Correction::Impossible { cycle } => writeln!(
+                f,
+                "  cannot be fixed, rules form a cycle through {}",
+                cycle.iter().join(",")
+            ),
+        }
+    }
+}
+
+/// Reports on every update in the input, in input order.
+pub fn report(input: &str) -> anyhow::Result<Vec<UpdateReport>> {
+    let PrintQueue { rules, updates } = parse(input)?;
+    Ok(updates.iter().map(|update| rules.report(update)).collect())
+}
+
+impl PageOrderingRules {
+    fn must_precede(&self, page: u32, follower: u32) -> bool {
+        self.followers
+            .get(&page)
+            .is_some_and(|followers| followers.contains(&follower))
+    }
+
+    /// Whether no rule between two pages of the update is violated.
+    pub fn is_ordered(&self, update: &[u32]) -> bool {
+        update.iter().enumerate().all(|(index, page)| {
+            update[index + 1..]
+                .iter()
+                .all(|later| !self.must_precede(*later, *page))
         })
-        .sum())
+    }
+
+    pub fn violations(&self, update: &[u32]) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (follower_position, follower) in update.iter().enumerate() {
+            for (page_position, page) in update.iter().enumerate().skip(follower_position + 1) {
+                if self.must_precede(*page, *follower) {
+                    violations.push(Violation {
+                        page: *page,
+                        follower: *follower,
+                        positions: [page_position, follower_position],
+                    });
+                }
+            }
+        }
+
+        violations
+    }
+
+    pub fn report(&self, update: &[u32]) -> UpdateReport {
+        let violations = self.violations(update);
+        let correction = if violations.is_empty() {
+            Correction::AlreadyOrdered
+        } else {
+            self.correct(update)
+        };
+
+        UpdateReport {
+            update: update.to_vec(),
+            violations,
+            correction,
+        }
+    }
+
+    /// Keeps the largest set of pages whose relative order is consistent with the transitive
+    /// closure of the rules, and moves every other page into place around them.
+    fn correct(&self, update: &[u32]) -> Correction {
+        let mut successors = self.rule_graph(update);
+        let closure = transitive_closure(&successors);
+
+        // Page `i` and a later page `j` are inverted when the rules require `j` before `i`.
+        // Inversions form a partial order, so the largest set of pages that can stay put is a
+        // maximum antichain, found from a maximum matching via König's theorem.
+        let inversions = (0..update.len())
+            .map(|i| {
+                (i + 1..update.len())
+                    .filter(|j| closure[*j][i])
+                    .collect::<Vec<usize>>()
+            })
+            .collect::<Vec<_>>();
+        let kept = maximum_antichain(&inversions);
+
+        for pair in kept.windows(2) {
+            successors[pair[0]].push(pair[1]);
+        }
+
+        match kahn(update, &successors) {
+            Kahn::Ordered { order, .. } => Correction::Reordered {
+                moves: update.len() - kept.len(),
+                order,
+            },
+            Kahn::Cycle(cycle) => Correction::Impossible { cycle },
+        }
+    }
+
+    /// Orders the update with Kahn's algorithm over the rules that mention only its pages.
+    pub fn topological_order(&self, update: &[u32]) -> TopologicalOrder {
+        match kahn(update, &self.rule_graph(update)) {
+            Kahn::Ordered {
+                order,
+                interchangeable: Some(interchangeable),
+            } => TopologicalOrder::Ambiguous {
+                order,
+                interchangeable,
+            },
+            Kahn::Ordered { order, .. } => TopologicalOrder::Unique(order),
+            Kahn::Cycle(cycle) => TopologicalOrder::Cycle(cycle),
+        }
+    }
+
+    /// The successors of each position in the update under the rules between its pages.
+    fn rule_graph(&self, update: &[u32]) -> Vec<Vec<usize>> {
+        update
+            .iter()
+            .map(|page| {
+                update
+                    .iter()
+                    .positions(|follower| self.must_precede(*page, *follower))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+enum Kahn {
+    Ordered {
+        order: Vec<u32>,
+        interchangeable: Option<Vec<u32>>,
+    },
+    Cycle(Vec<u32>),
 }
 
-fn parse(input: &str) -> IResult<&str, (Multimap<u32, u32>, Vec<Vec<u32>>)> {
+/// Topologically sorts positions of the update, breaking ties by position.
+fn kahn(update: &[u32], successors: &[Vec<usize>]) -> Kahn {
+    let mut predecessors = vec![Vec::new(); update.len()];
+    for (index, followers) in successors.iter().enumerate() {
+        for follower in followers {
+            predecessors[*follower].push(index);
+        }
+    }
+
+    let mut in_degrees = predecessors.iter().map(Vec::len).collect::<Vec<usize>>();
+    let mut available = (0..update.len())
+        .filter(|index| in_degrees[*index] == 0)
+        .map(Reverse)
+        .collect::<BinaryHeap<Reverse<usize>>>();
+    let mut order = Vec::with_capacity(update.len());
+    let mut interchangeable: Option<Vec<u32>> = None;
+
+    while let Some(Reverse(index)) = available.peek().copied() {
+        if available.len() > 1 && interchangeable.is_none() {
+            let mut pages = available.iter().map(|Reverse(i)| *i).collect::<Vec<_>>();
+            pages.sort();
+            interchangeable = Some(pages.into_iter().map(|i| update[i]).collect());
+        }
+        available.pop();
+        order.push(update[index]);
+
+        for successor in &successors[index] {
+            in_degrees[*successor] -= 1;
+            if in_degrees[*successor] == 0 {
+                available.push(Reverse(*successor));
+            }
+        }
+    }
+
+    if order.len() < update.len() {
+        Kahn::Cycle(find_cycle(update, &predecessors, &in_degrees))
+    } else {
+        Kahn::Ordered {
+            order,
+            interchangeable,
+        }
+    }
+}
+
+/// Walks backwards through the pages Kahn's algorithm could not place, each of which has an
+/// unplaced predecessor, until a page repeats.
+fn find_cycle(update: &[u32], predecessors: &[Vec<usize>], in_degrees: &[usize]) -> Vec<u32> {
+    let Some(mut current) = (0..update.len()).find(|index| in_degrees[*index] > 0) else {
+        return Vec::new();
+    };
+
+    let mut visited_at = HashMap::<usize, usize>::new();
+    let mut walk = Vec::new();
+    while !visited_at.contains_key(&current) {
+        visited_at.insert(current, walk.len());
+        walk.push(current);
+        current = *predecessors[current]
+            .iter()
+            .find(|predecessor| in_degrees[**predecessor] > 0)
+            .expect("Unplaced pages always have an unplaced predecessor");
+    }
+
+    walk[visited_at[&current]..]
+        .iter()
+        .rev()
+        .map(|index| update[*index])
+        .collect()
+}
+
+fn transitive_closure(successors: &[Vec<usize>]) -> Vec<Vec<bool>> {
+    successors
+        .iter()
+        .enumerate()
+        .map(|(start, _)| {
+            let mut reachable = vec![false; successors.len()];
+            let mut stack = successors[start].clone();
+            while let Some(index) = stack.pop() {
+                if !reachable[index] {
+                    reachable[index] = true;
+                    stack.extend(&successors[index]);
+                }
+            }
+            reachable
+        })
+        .collect()
+}
+
+/// Returns the indices, in increasing order, of a maximum antichain of the strict partial order
+/// in which `greater[i]` lists the elements above `i`.
+fn maximum_antichain(greater: &[Vec<usize>]) -> Vec<usize> {
+    let mut left_of_right: Vec<Option<usize>> = vec![None; greater.len()];
+    for left in 0..greater.len() {
+        augment(
+            greater,
+            left,
+            &mut left_of_right,
+            &mut vec![false; greater.len()],
+        );
+    }
+
+    let mut matched_left = vec![false; greater.len()];
+    for left in left_of_right.iter().flatten() {
+        matched_left[*left] = true;
+    }
+
+    // König's theorem: vertices reachable by alternating paths from unmatched left vertices
+    // determine a minimum vertex cover, and elements outside it form the antichain.
+    let mut reached_left = vec![false; greater.len()];
+    let mut reached_right = vec![false; greater.len()];
+    let mut stack = (0..greater.len())
+        .filter(|left| !matched_left[*left])
+        .collect::<Vec<usize>>();
+    while let Some(left) = stack.pop() {
+        if reached_left[left] {
+            continue;
+        }
+        reached_left[left] = true;
+        for right in &greater[left] {
+            if !reached_right[*right] {
+                reached_right[*right] = true;
+                stack.extend(left_of_right[*right]);
+            }
+        }
+    }
+
+    (0..greater.len())
+        .filter(|index| reached_left[*index] && !reached_right[*index])
+        .collect()
+}
+
+/// Kuhn's augmenting path search for a maximum bipartite matching.
+fn augment(
+    edges: &[Vec<usize>],
+    left: usize,
+    left_of_right: &mut [Option<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for right in &edges[left] {
+        if visited[*right] {
+            continue;
+        }
+        visited[*right] = true;
+
+        if left_of_right[*right]
+            .is_none_or(|matched| augment(edges, matched, left_of_right, visited))
+        {
+            left_of_right[*right] = Some(left);
+            return true;
+        }
+    }
+
+    false
+}
+
+pub fn parse(input: &str) -> anyhow::Result<PrintQueue> {
+    let (_, (followers, updates)) =
+        print_queue(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
+    Ok(PrintQueue {
+        rules: PageOrderingRules { followers },
+        updates,
+    })
+}
+
+fn print_queue(input: &str) -> IResult<&str, (Multimap<u32, u32>, Vec<Update>)> {
     separated_pair(rules, line_ending, updates)(input)
 }
 
@@ -88,7 +440,7 @@ fn rules(input: &str) -> IResult<&str, Multimap<u32, u32>> {
     )(input)
 }
 
-fn updates(input: &str) -> IResult<&str, Vec<Vec<u32>>> {
+fn updates(input: &str) -> IResult<&str, Vec<Update>> {
     separated_list1(
         line_ending,
         separated_list1(character::complete::char(','), character::complete::u32),
@@ -97,7 +449,7 @@ fn updates(input: &str) -> IResult<&str, Vec<Vec<u32>>> {
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{parse, part1, part2, report, Correction, TopologicalOrder, Violation};
 
     const INPUT: &str = "47|53
 97|13
@@ -139,4 +491,60 @@ mod tests {
         assert_eq!(part2(INPUT)?, 123);
         Ok(())
     }
+
+    #[test]
+    fn topological_order_detects_cycles() -> anyhow::Result<()> {
+        let queue = parse("1|2\n2|3\n3|1\n3|4\n\n4,3,2,1")?;
+
+        assert_eq!(
+            queue.rules.topological_order(&queue.updates[0]),
+            TopologicalOrder::Cycle(vec![1, 2, 3])
+        );
+        assert!(part2("1|2\n2|3\n3|1\n3|4\n\n4,3,2,1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn topological_order_reports_ambiguity() -> anyhow::Result<()> {
+        let queue = parse("5|1\n5|2\n\n2,1,5")?;
+
+        assert_eq!(
+            queue.rules.topological_order(&queue.updates[0]),
+            TopologicalOrder::Ambiguous {
+                order: vec![5, 2, 1],
+                interchangeable: vec![2, 1],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn report_lists_violations_and_minimal_corrections() -> anyhow::Result<()> {
+        let reports = report(INPUT)?;
+
+        assert_eq!(reports[0].correction, Correction::AlreadyOrdered);
+        assert_eq!(
+            reports[3].violations,
+            vec![Violation {
+                page: 97,
+                follower: 75,
+                positions: [1, 0],
+            }]
+        );
+        assert_eq!(
+            reports[3].correction,
+            Correction::Reordered {
+                moves: 1,
+                order: vec![97, 75, 47, 61, 53],
+            }
+        );
+        assert_eq!(
+            reports[5].correction,
+            Correction::Reordered {
+                moves: 1,
+                order: vec![97, 75, 47, 29],
+            }
+        );
+        Ok(())
+    }
 }
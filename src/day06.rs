@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use anyhow::anyhow;
-use ndarray::Array2;
+use ndarray::{Array2, Array3};
 use nom::{
     branch::alt,
     character::{self, complete::line_ending},
@@ -9,108 +9,310 @@ use nom::{
     multi::{many1, separated_list1},
     IResult,
 };
+use rayon::prelude::*;
 
 pub fn part1(input: &str) -> anyhow::Result<usize> {
-    let map = parse(input)?;
-    let mut starting_position = [0, 0];
-    map.indexed_iter().position(|((i, j), element)| {
-        if *element == Tile::GuardStart {
-            starting_position = [i, j];
-            true
-        } else {
-            false
+    let lab = Lab::parse(input)?;
+    let mut visited = HashSet::new();
+    for patrol in lab.patrols(TurnPolicy::Right) {
+        if let PatrolOutcome::Loops { .. } = patrol.outcome {
+            return Err(anyhow!("Guard unexpectedly loops"));
         }
-    });
-    let simulation_result = simulate_guard(&map, &starting_position, false);
-
-    match simulation_result {
-        SimulationResult::LeavesMap {
-            num_visited_tiles,
-            path: _,
-        } => Ok(num_visited_tiles),
-        SimulationResult::Loops => Err(anyhow!("Guard unexpectedly loops")),
+        visited.extend(patrol.visited);
     }
+
+    Ok(visited.len())
 }
 
 pub fn part2(input: &str) -> anyhow::Result<usize> {
-    let map = parse(input)?;
-    let mut starting_position = [0, 0];
-    map.indexed_iter().position(|((i, j), element)| {
-        if *element == Tile::GuardStart {
-            starting_position = [i, j];
-            true
-        } else {
-            false
+    let lab = Lab::parse(input)?;
+    let [guard] = lab.guards.as_slice() else {
+        return Err(anyhow!(
+            "Expected exactly one guard, found {}",
+            lab.guards.len()
+        ));
+    };
+
+    let Patrol {
+        path,
+        outcome: PatrolOutcome::LeavesMap,
+        ..
+    } = lab.patrol(guard, TurnPolicy::Right)
+    else {
+        return Err(anyhow!("Unexpectedly looped"));
+    };
+
+    // Each candidate obstacle only changes the route after the guard first reaches it, so its
+    // simulation resumes from the state just before that point.
+    let mut considered = Array2::<bool>::from_elem(lab.map.dim(), false);
+    let mut candidates = Vec::new();
+    for (position, direction) in &path[..(path.len() - 1)] {
+        let obstacle_position = step(position, direction);
+        let next_tile = &lab.map[(obstacle_position[0], obstacle_position[1])];
+        if *next_tile == Tile::Obstacle || obstacle_position == guard.position {
+            continue;
         }
-    });
-    let unmodified_simulation = simulate_guard(&map, &starting_position, true);
-    let mut proposed_obstacles: HashSet<[usize; 2]> = HashSet::new();
+        if std::mem::replace(
+            &mut considered[(obstacle_position[0], obstacle_position[1])],
+            true,
+        ) {
+            continue;
+        }
+        candidates.push((obstacle_position, *position, *direction));
+    }
 
-    match unmodified_simulation {
-        SimulationResult::LeavesMap {
-            num_visited_tiles: _,
-            path,
-        } => {
-            for (position, direction) in &path[..(path.len() - 1)] {
-                let obstacle_position = step(position, direction);
-                let next_tile = &map[(obstacle_position[0], obstacle_position[1])];
-                if *next_tile == Tile::Obstacle || *next_tile == Tile::GuardStart {
-                    continue;
-                }
-                let mut modified_map = map.clone();
-                modified_map[(obstacle_position[0], obstacle_position[1])] = Tile::Obstacle;
-                if simulate_guard(&modified_map, &starting_position, false)
-                    == SimulationResult::Loops
-                {
-                    proposed_obstacles.insert(obstacle_position);
+    let jump_table = JumpTable::new(&lab.map);
+    Ok(candidates
+        .par_iter()
+        .filter(|(obstacle_position, position, direction)| {
+            jump_table.loops(position, direction, obstacle_position)
+        })
+        .count())
+}
+
+/// A map of the lab and the guards patrolling it. Guards are not obstacles to each other.
+pub struct Lab {
+    map: Array2<Tile>,
+    guards: Vec<Guard>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guard {
+    pub position: [usize; 2],
+    pub direction: Direction,
+}
+
+/// Which way a guard turns on reaching an obstacle. `Alternating` turns right first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnPolicy {
+    Right,
+    Left,
+    Alternating,
+}
+
+impl TurnPolicy {
+    fn turn(&self, direction: &Direction, turns_taken: usize) -> Direction {
+        match self {
+            TurnPolicy::Right => direction.turn_right(),
+            TurnPolicy::Left => direction.turn_left(),
+            TurnPolicy::Alternating if turns_taken.is_multiple_of(2) => direction.turn_right(),
+            TurnPolicy::Alternating => direction.turn_left(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patrol {
+    /// Every position and direction the guard takes, one entry per step or turn.
+    pub path: Vec<([usize; 2], Direction)>,
+    pub visited: HashSet<[usize; 2]>,
+    pub outcome: PatrolOutcome,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatrolOutcome {
+    LeavesMap,
+    /// The guard repeats `cycle` forever, returning to its first state after the last.
+    Loops {
+        cycle: Vec<([usize; 2], Direction)>,
+    },
+}
+
+impl Lab {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let map = parse(input)?;
+        let guards = map
+            .indexed_iter()
+            .filter_map(|((row, col), tile)| match tile {
+                Tile::Guard(direction) => Some(Guard {
+                    position: [row, col],
+                    direction: *direction,
+                }),
+                _ => None,
+            })
+            .collect::<Vec<Guard>>();
+
+        if guards.is_empty() {
+            return Err(anyhow!("Map has no guard"));
+        }
+
+        Ok(Lab { map, guards })
+    }
+
+    pub fn guards(&self) -> &[Guard] {
+        &self.guards
+    }
+
+    pub fn patrols(&self, policy: TurnPolicy) -> Vec<Patrol> {
+        self.guards
+            .iter()
+            .map(|guard| self.patrol(guard, policy))
+            .collect()
+    }
+
+    pub fn patrol(&self, guard: &Guard, policy: TurnPolicy) -> Patrol {
+        let mut position = guard.position;
+        let mut direction = guard.direction;
+        let mut turns_taken = 0;
+        let mut path = Vec::from([(position, direction)]);
+
+        // Alternating guards can pass the same state facing opposite turns, so the parity of
+        // the turn count is part of the state.
+        let parity = |turns_taken: usize| match policy {
+            TurnPolicy::Alternating => turns_taken % 2,
+            TurnPolicy::Right | TurnPolicy::Left => 0,
+        };
+        let mut first_seen =
+            Array3::<Option<usize>>::from_elem((self.map.dim().0, self.map.dim().1, 8), None);
+        first_seen[(position[0], position[1], direction.index())] = Some(0);
+
+        while !leaving_map(&self.map, &position, &direction) {
+            let next_position = step(&position, &direction);
+            match self.map[(next_position[0], next_position[1])] {
+                Tile::Empty | Tile::Guard(_) => position = next_position,
+                Tile::Obstacle => {
+                    direction = policy.turn(&direction, turns_taken);
+                    turns_taken += 1;
                 }
             }
+
+            let state = (
+                position[0],
+                position[1],
+                direction.index() + 4 * parity(turns_taken),
+            );
+            if let Some(start) = first_seen[state] {
+                let visited = path.iter().map(|(position, _)| *position).collect();
+                return Patrol {
+                    outcome: PatrolOutcome::Loops {
+                        cycle: path[start..].to_vec(),
+                    },
+                    path,
+                    visited,
+                };
+            }
+            first_seen[state] = Some(path.len());
+            path.push((position, direction));
         }
-        SimulationResult::Loops => return Err(anyhow!("Unexpectedly looped")),
-    };
 
-    Ok(proposed_obstacles.len())
+        let visited = path.iter().map(|(position, _)| *position).collect();
+        Patrol {
+            path,
+            visited,
+            outcome: PatrolOutcome::LeavesMap,
+        }
+    }
+}
+
+/// For every position and direction, where a guard walking from that position stops in front of
+/// the next obstacle, or `None` if it leaves the map first.
+struct JumpTable {
+    stops: Array3<Option<[usize; 2]>>,
 }
 
-fn simulate_guard(
-    map: &Array2<Tile>,
-    starting_position: &[usize; 2],
-    record_path: bool,
-) -> SimulationResult {
-    let mut position = *starting_position;
-    let mut direction = Direction::Up;
-    let mut visited_tiles: Array2<u8> = Array2::zeros(map.dim());
-    let mut path = Vec::from([(position, direction)]);
+impl JumpTable {
+    fn new(map: &Array2<Tile>) -> Self {
+        let (num_rows, num_cols) = map.dim();
+        let mut stops = Array3::from_elem((num_rows, num_cols, 4), None);
+        let obstacle = |row: usize, col: usize| map[(row, col)] == Tile::Obstacle;
 
-    while !leaving_map(map, &position, &direction) {
-        let next_position = step(&position, &direction);
-        match map[(next_position[0], next_position[1])] {
-            Tile::Empty | Tile::GuardStart => position = next_position,
-            Tile::Obstacle => direction = direction.turn_right(),
+        for col in 0..num_cols {
+            let mut stop = None;
+            for row in 0..num_rows {
+                stops[(row, col, Direction::Up.index())] = stop;
+                if obstacle(row, col) {
+                    stop = Some([row + 1, col]);
+                }
+            }
+
+            stop = None;
+            for row in (0..num_rows).rev() {
+                stops[(row, col, Direction::Down.index())] = stop;
+                if obstacle(row, col) && row > 0 {
+                    stop = Some([row - 1, col]);
+                }
+            }
         }
-        if record_path {
-            path.push((position, direction));
+
+        for row in 0..num_rows {
+            let mut stop = None;
+            for col in 0..num_cols {
+                stops[(row, col, Direction::Left.index())] = stop;
+                if obstacle(row, col) {
+                    stop = Some([row, col + 1]);
+                }
+            }
+
+            stop = None;
+            for col in (0..num_cols).rev() {
+                stops[(row, col, Direction::Right.index())] = stop;
+                if obstacle(row, col) && col > 0 {
+                    stop = Some([row, col - 1]);
+                }
+            }
         }
-        if visited_tiles[(position[0], position[1])] & direction.get_bit_mask() != 0 {
-            return SimulationResult::Loops;
+
+        JumpTable { stops }
+    }
+
+    /// Where a guard walking from `position` stops, taking one extra obstacle into account.
+    fn jump(
+        &self,
+        position: &[usize; 2],
+        direction: &Direction,
+        extra_obstacle: &[usize; 2],
+    ) -> Option<[usize; 2]> {
+        let stop = self.stops[(position[0], position[1], direction.index())];
+        let Some(obstacle_distance) = distance(position, extra_obstacle, direction) else {
+            return stop;
+        };
+
+        let stop_distance = stop.map_or(usize::MAX, |stop| {
+            distance(position, &stop, direction).unwrap_or(0)
+        });
+        if obstacle_distance <= stop_distance {
+            let mut stop = *position;
+            for _ in 1..obstacle_distance {
+                stop = step(&stop, direction);
+            }
+            Some(stop)
         } else {
-            visited_tiles[(position[0], position[1])] |= direction.get_bit_mask();
+            stop
         }
     }
 
-    SimulationResult::LeavesMap {
-        num_visited_tiles: visited_tiles.iter().filter(|mask| **mask != 0).count(),
-        path,
+    /// Whether a guard at `position` facing `direction` loops once `extra_obstacle` is added.
+    /// Only the points where the guard turns are tracked.
+    fn loops(
+        &self,
+        position: &[usize; 2],
+        direction: &Direction,
+        extra_obstacle: &[usize; 2],
+    ) -> bool {
+        let mut position = *position;
+        let mut direction = *direction;
+        let mut turns = HashSet::<([usize; 2], Direction)>::new();
+
+        while let Some(stop) = self.jump(&position, &direction, extra_obstacle) {
+            if !turns.insert((stop, direction)) {
+                return true;
+            }
+            position = stop;
+            direction = direction.turn_right();
+        }
+
+        false
     }
 }
 
-#[derive(PartialEq, Eq)]
-enum SimulationResult {
-    LeavesMap {
-        num_visited_tiles: usize,
-        path: Vec<([usize; 2], Direction)>,
-    },
-    Loops,
+/// How many steps in `direction` lead from `from` to `to`, if any.
+fn distance(from: &[usize; 2], to: &[usize; 2], direction: &Direction) -> Option<usize> {
+    match direction {
+        Direction::Up => (from[1] == to[1] && to[0] <= from[0]).then(|| from[0] - to[0]),
+        Direction::Down => (from[1] == to[1] && to[0] >= from[0]).then(|| to[0] - from[0]),
+        Direction::Left => (from[0] == to[0] && to[1] <= from[1]).then(|| from[1] - to[1]),
+        Direction::Right => (from[0] == to[0] && to[1] >= from[1]).then(|| to[1] - from[1]),
+    }
 }
 
 fn leaving_map(map: &Array2<Tile>, position: &[usize; 2], direction: &Direction) -> bool {
@@ -123,7 +325,7 @@ fn leaving_map(map: &Array2<Tile>, position: &[usize; 2], direction: &Direction)
 }
 
 fn parse(input: &str) -> anyhow::Result<Array2<Tile>> {
-    let (_, map) = separated_list1(line_ending, many1(alt((empty, obstacle, guard_start))))(input)
+    let (_, map) = separated_list1(line_ending, many1(alt((empty, obstacle, guard))))(input)
         .map_err(|e| anyhow!("{}", e))?;
     let num_rows = map.len();
     let num_cols = map[0].len();
@@ -139,8 +341,16 @@ fn obstacle(input: &str) -> IResult<&str, Tile> {
     value(Tile::Obstacle, character::complete::char('#'))(input)
 }
 
-fn guard_start(input: &str) -> IResult<&str, Tile> {
-    value(Tile::GuardStart, character::complete::char('^'))(input)
+fn guard(input: &str) -> IResult<&str, Tile> {
+    alt((
+        value(Tile::Guard(Direction::Up), character::complete::char('^')),
+        value(
+            Tile::Guard(Direction::Right),
+            character::complete::char('>'),
+        ),
+        value(Tile::Guard(Direction::Down), character::complete::char('v')),
+        value(Tile::Guard(Direction::Left), character::complete::char('<')),
+    ))(input)
 }
 
 fn step(position: &[usize; 2], direction: &Direction) -> [usize; 2] {
@@ -156,11 +366,11 @@ fn step(position: &[usize; 2], direction: &Direction) -> [usize; 2] {
 enum Tile {
     Empty,
     Obstacle,
-    GuardStart,
+    Guard(Direction),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-enum Direction {
+pub enum Direction {
     Up,
     Down,
     Left,
@@ -177,19 +387,28 @@ impl Direction {
         }
     }
 
-    fn get_bit_mask(&self) -> u8 {
+    fn turn_left(&self) -> Self {
+        match self {
+            Self::Up => Self::Left,
+            Self::Left => Self::Down,
+            Self::Down => Self::Right,
+            Self::Right => Self::Up,
+        }
+    }
+
+    fn index(&self) -> usize {
         match self {
-            Direction::Up => 0b00000001,
-            Direction::Down => 0b00000010,
-            Direction::Left => 0b00000100,
-            Direction::Right => 0b00001000,
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{part1, part2, Direction, Lab, PatrolOutcome, TurnPolicy};
 
     const INPUT: &str = "....#.....
 .........#
@@ -213,4 +432,67 @@ mod tests {
         assert_eq!(part2(INPUT)?, 6);
         Ok(())
     }
+
+    #[test]
+    fn patrols_follow_each_guard_and_report_cycles() -> anyhow::Result<()> {
+        let lab = Lab::parse(".#..\n.^.#\n#...\n>.#.")?;
+        assert_eq!(
+            lab.guards()
+                .iter()
+                .map(|guard| guard.direction)
+                .collect::<Vec<Direction>>(),
+            vec![Direction::Up, Direction::Right]
+        );
+
+        let patrols = lab.patrols(TurnPolicy::Right);
+        let PatrolOutcome::Loops { cycle } = &patrols[0].outcome else {
+            panic!("First guard should loop");
+        };
+        assert_eq!(cycle.len(), 8);
+        assert_eq!(cycle[0], ([1, 1], Direction::Up));
+        assert_eq!(patrols[0].visited.len(), 4);
+        assert_eq!(patrols[1].outcome, PatrolOutcome::LeavesMap);
+        assert_eq!(patrols[1].visited.len(), 2);
+
+        let left_patrol = lab.patrol(&lab.guards()[1], TurnPolicy::Left);
+        assert_eq!(left_patrol.outcome, PatrolOutcome::LeavesMap);
+        assert_eq!(left_patrol.visited.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn alternating_guards_turn_back_and_forth() -> anyhow::Result<()> {
+        // Turning right then left leaves the guard facing the obstacle it just turned away from.
+        let lab = Lab::parse(".#.\n.^#")?;
+        let guard = &lab.guards()[0];
+        assert_eq!(
+            lab.patrol(guard, TurnPolicy::Alternating).outcome,
+            PatrolOutcome::Loops {
+                cycle: vec![([1, 1], Direction::Up), ([1, 1], Direction::Right)]
+            }
+        );
+        assert_eq!(
+            lab.patrol(guard, TurnPolicy::Right).outcome,
+            PatrolOutcome::LeavesMap
+        );
+
+        let lab = Lab::parse("#...\n#...\n#..#\n^..#")?;
+        let patrol = lab.patrol(&lab.guards()[0], TurnPolicy::Alternating);
+        assert_eq!(patrol.outcome, PatrolOutcome::LeavesMap);
+        assert_eq!(
+            patrol.path,
+            vec![
+                ([3, 0], Direction::Up),
+                ([3, 0], Direction::Right),
+                ([3, 1], Direction::Right),
+                ([3, 2], Direction::Right),
+                ([3, 2], Direction::Up),
+                ([2, 2], Direction::Up),
+                ([1, 2], Direction::Up),
+                ([0, 2], Direction::Up),
+            ]
+        );
+        assert_eq!(patrol.visited.len(), 6);
+        Ok(())
+    }
 }
@@ -1,13 +1,13 @@
-use std::iter::zip;
+use std::collections::HashSet;
 
 use anyhow::anyhow;
-use itertools::Itertools;
 use nom::{
     bytes::complete::tag,
     character::{
         self,
         complete::{line_ending, space1},
     },
+    combinator::map,
     multi::separated_list1,
     sequence::separated_pair,
     IResult,
@@ -15,93 +15,501 @@ use nom::{
 use rayon::prelude::*;
 
 pub fn part1(input: &str) -> anyhow::Result<u64> {
-    let (_, calibration_equations) =
-        parse(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
+    total_calibration_result(input, &OperatorSet::new().with(Add).with(Multiply))
+}
 
-    let operators = [Operator::Add, Operator::Multiply];
+pub fn part2(input: &str) -> anyhow::Result<u64> {
+    total_calibration_result(
+        input,
+        &OperatorSet::new()
+            .with(Add)
+            .with(Multiply)
+            .with(Concatenate::DECIMAL),
+    )
+}
 
-    let total_calibration_result = calibration_equations
+fn total_calibration_result(input: &str, operators: &OperatorSet) -> anyhow::Result<u64> {
+    parse_equations(input)?
         .par_iter()
-        .filter_map(|(solution, operands)| {
-            let num_operators = operands.len() - 1;
-            (0..num_operators)
-                .map(|_| operators)
-                .multi_cartesian_product()
-                .any(|operator_sequence| {
-                    let value = zip(operands[1..].iter(), operator_sequence.iter()).fold(
-                        operands[0],
-                        |acc, (operand, operator)| match operator {
-                            Operator::Add => acc + operand,
-                            Operator::Multiply => acc * operand,
-                            Operator::Concatenate => panic!("Unexpected concatenate"),
-                        },
-                    );
-
-                    value == *solution
-                })
-                .then_some(solution)
+        .filter(|equation| operators.solvable(equation))
+        .map(|equation| Some(equation.target))
+        .try_reduce(|| 0, |a, b| a.checked_add(b))
+        .ok_or(anyhow!("Total calibration result overflows u64"))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Equation {
+    pub target: u64,
+    pub operands: Vec<u64>,
+}
+
+pub fn parse_equations(input: &str) -> anyhow::Result<Vec<Equation>> {
+    let (_, equations) = parse(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
+    Ok(equations)
+}
+
+/// How a sequence of operators is evaluated. Both treat every operator as left-associative.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Evaluation {
+    #[default]
+    LeftToRight,
+    /// Operators with higher [`Operator::precedence`] bind more tightly.
+    Precedence,
+}
+
+/// A binary operator on unsigned integers that may be placed between two operands.
+pub trait Operator: Send + Sync {
+    fn symbol(&self) -> String;
+
+    /// Applies the operator, or returns `None` if the result is not a `u64`.
+    fn apply(&self, left: u64, right: u64) -> Option<u64>;
+
+    /// The left operands for which `apply(left, right) == result`. Operators that cannot be
+    /// undone return `None` for every input, and equations using them are searched forwards.
+    fn undo(&self, _result: u64, _right: u64) -> Option<Predecessor> {
+        None
+    }
+
+    fn precedence(&self) -> u8 {
+        0
+    }
+}
+
+pub enum Predecessor {
+    Exactly(u64),
+    /// Every left operand gives the result, as when multiplying by zero gives zero.
+    Any,
+    None,
+}
+
+impl Predecessor {
+    fn from_option(previous: Option<u64>) -> Self {
+        previous.map_or(Predecessor::None, Predecessor::Exactly)
+    }
+}
+
+pub struct Add;
+
+impl Operator for Add {
+    fn symbol(&self) -> String {
+        "+".to_string()
+    }
+
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        left.checked_add(right)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(Predecessor::from_option(result.checked_sub(right)))
+    }
+}
+
+pub struct Subtract;
+
+impl Operator for Subtract {
+    fn symbol(&self) -> String {
+        "-".to_string()
+    }
+
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        left.checked_sub(right)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(Predecessor::from_option(result.checked_add(right)))
+    }
+}
+
+pub struct Multiply;
+
+impl Operator for Multiply {
+    fn symbol(&self) -> String {
+        "*".to_string()
+    }
+
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        left.checked_mul(right)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(match (result, right) {
+            (0, 0) => Predecessor::Any,
+            (_, 0) => Predecessor::None,
+            _ => Predecessor::from_option(result.is_multiple_of(right).then(|| result / right)),
         })
-        .sum();
+    }
 
-    Ok(total_calibration_result)
+    fn precedence(&self) -> u8 {
+        1
+    }
 }
 
-pub fn part2(input: &str) -> anyhow::Result<u64> {
-    let (_, calibration_equations) =
-        parse(input).map_err(|e| anyhow!("Unable to parse input: {}", e))?;
-    let operators = [Operator::Add, Operator::Multiply, Operator::Concatenate];
+pub struct Exponent;
 
-    let total_calibration_result = calibration_equations
-        .par_iter()
-        .filter_map(|(solution, operands)| {
-            let num_operators = operands.len() - 1;
-            (0..num_operators)
-                .map(|_| operators)
-                .multi_cartesian_product()
-                .any(|operator_sequence| {
-                    let value = zip(operands[1..].iter(), operator_sequence.iter()).fold(
-                        operands[0],
-                        |acc, (operand, operator)| match operator {
-                            Operator::Add => acc + operand,
-                            Operator::Multiply => acc * operand,
-                            Operator::Concatenate => concatenate(&acc, operand),
-                        },
-                    );
-
-                    value == *solution
-                })
-                .then_some(solution)
+impl Operator for Exponent {
+    fn symbol(&self) -> String {
+        "^".to_string()
+    }
+
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        left.checked_pow(right.try_into().ok()?)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(match (result, right) {
+            (1, 0) => Predecessor::Any,
+            (_, 0) => Predecessor::None,
+            _ => Predecessor::from_option(exact_root(result, right)),
         })
-        .sum();
+    }
+
+    fn precedence(&self) -> u8 {
+        2
+    }
+}
+
+pub struct Xor;
+
+impl Operator for Xor {
+    fn symbol(&self) -> String {
+        "^^".to_string()
+    }
+
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        Some(left ^ right)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(Predecessor::Exactly(result ^ right))
+    }
+}
+
+/// Appends the digits of the right operand, written in `base`, to those of the left.
+pub struct Concatenate {
+    base: u64,
+}
+
+impl Concatenate {
+    pub const DECIMAL: Concatenate = Concatenate { base: 10 };
+
+    pub fn new(base: u64) -> anyhow::Result<Self> {
+        if base < 2 {
+            return Err(anyhow!("Concatenation base must be at least 2, got {base}"));
+        }
+
+        Ok(Concatenate { base })
+    }
+
+    /// The power of the base that shifts a number left past every digit of `operand`.
+    fn scale(&self, operand: u64) -> Option<u64> {
+        self.base
+            .checked_pow(operand.checked_ilog(self.base).unwrap_or(0) + 1)
+    }
+}
+
+impl Operator for Concatenate {
+    fn symbol(&self) -> String {
+        match self.base {
+            10 => "||".to_string(),
+            base => format!("||{base}"),
+        }
+    }
 
-    Ok(total_calibration_result)
+    fn apply(&self, left: u64, right: u64) -> Option<u64> {
+        self.scale(right)?.checked_mul(left)?.checked_add(right)
+    }
+
+    fn undo(&self, result: u64, right: u64) -> Option<Predecessor> {
+        Some(Predecessor::from_option(
+            self.scale(right)
+                .filter(|scale| result % scale == right)
+                .map(|scale| result / scale),
+        ))
+    }
+}
+
+/// The largest integer whose `exponent`-th power is exactly `value`, if one exists.
+fn exact_root(value: u64, exponent: u64) -> Option<u64> {
+    let exponent = u32::try_from(exponent).ok()?;
+    let (mut low, mut high) = (0, value);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        match mid.checked_pow(exponent) {
+            Some(power) if power <= value => low = mid,
+            _ => high = mid - 1,
+        }
+    }
+
+    (low.checked_pow(exponent) == Some(value)).then_some(low)
 }
 
-fn concatenate(a: &u64, b: &u64) -> u64 {
-    a * (10_u64.pow(b.ilog10() + 1)) + b
+#[derive(Default)]
+pub struct OperatorSet {
+    operators: Vec<Box<dyn Operator>>,
+    evaluation: Evaluation,
+}
+
+/// A solution, given as indices into the [`OperatorSet`] of the operator placed between each
+/// pair of consecutive operands.
+pub type OperatorSequence = Vec<usize>;
+
+struct Search {
+    solutions: Vec<OperatorSequence>,
+    first_only: bool,
 }
 
-fn parse(input: &str) -> IResult<&str, Vec<(u64, Vec<u64>)>> {
+impl Search {
+    fn done(&self) -> bool {
+        self.first_only && !self.solutions.is_empty()
+    }
+}
+
+impl OperatorSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, operator: impl Operator + 'static) -> Self {
+        self.operators.push(Box::new(operator));
+        self
+    }
+
+    pub fn with_evaluation(mut self, evaluation: Evaluation) -> Self {
+        self.evaluation = evaluation;
+        self
+    }
+
+    pub fn solvable(&self, equation: &Equation) -> bool {
+        !self.search(equation, true).is_empty()
+    }
+
+    /// Every operator sequence that turns the equation's operands into its target.
+    pub fn solutions(&self, equation: &Equation) -> Vec<OperatorSequence> {
+        self.search(equation, false)
+    }
+
+    /// Writes the equation out with the given operators, e.g. `3267 = 81 + 40 * 27`.
+    pub fn render(&self, equation: &Equation, sequence: &[usize]) -> String {
+        let mut expression = equation
+            .operands
+            .first()
+            .map_or(String::new(), u64::to_string);
+        for (operator, operand) in sequence.iter().zip(&equation.operands[1..]) {
+            expression += &format!(" {} {operand}", self.operators[*operator].symbol());
+        }
+
+        format!("{} = {expression}", equation.target)
+    }
+
+    fn search(&self, equation: &Equation, first_only: bool) -> Vec<OperatorSequence> {
+        let mut search = Search {
+            solutions: Vec::new(),
+            first_only,
+        };
+        let Some((first, rest)) = equation.operands.split_first() else {
+            return search.solutions;
+        };
+
+        let invertible = self
+            .operators
+            .iter()
+            .all(|operator| operator.undo(0, 0).is_some());
+        match self.evaluation {
+            Evaluation::LeftToRight if invertible => self.reverse_search(
+                equation.target,
+                &equation.operands,
+                &mut Vec::new(),
+                &mut HashSet::new(),
+                &mut search,
+            ),
+            Evaluation::LeftToRight => self.forward_search(
+                *first,
+                rest,
+                Some(equation.target),
+                &mut Vec::new(),
+                &[],
+                &mut search,
+            ),
+            Evaluation::Precedence => {
+                self.exhaustive_search(equation, &mut Vec::new(), &mut search)
+            }
+        };
+
+        search.solutions.sort();
+        search.solutions
+    }
+
+    /// Works backwards from the target, undoing the last operation at each step, so that only
+    /// predecessors that could actually lead to the target are explored. `suffix` holds the
+    /// operators chosen so far, last operator first. Returns whether a solution was found.
+    fn reverse_search(
+        &self,
+        target: u64,
+        operands: &[u64],
+        suffix: &mut Vec<usize>,
+        dead_ends: &mut HashSet<(usize, u64)>,
+        search: &mut Search,
+    ) -> bool {
+        let Some((last, rest)) = operands.split_last() else {
+            return false;
+        };
+        if rest.is_empty() {
+            if target == *last {
+                search
+                    .solutions
+                    .push(suffix.iter().rev().copied().collect());
+            }
+            return target == *last;
+        }
+        if dead_ends.contains(&(rest.len(), target)) {
+            return false;
+        }
+
+        let mut found = false;
+        for (index, operator) in self.operators.iter().enumerate() {
+            suffix.push(index);
+            found |= match operator.undo(target, *last) {
+                Some(Predecessor::Exactly(previous)) => {
+                    self.reverse_search(previous, rest, suffix, dead_ends, search)
+                }
+                Some(Predecessor::Any) => {
+                    let tail = suffix.iter().rev().copied().collect::<Vec<usize>>();
+                    self.forward_search(rest[0], &rest[1..], None, &mut Vec::new(), &tail, search)
+                }
+                Some(Predecessor::None) | None => false,
+            };
+            suffix.pop();
+
+            if search.done() {
+                break;
+            }
+        }
+
+        if !found {
+            dead_ends.insert((rest.len(), target));
+        }
+        found
+    }
+
+    /// Evaluates every operator sequence left to right, pruning those that overflow. A `target`
+    /// of `None` accepts any value. Each solution is followed by the operators in `tail`.
+    fn forward_search(
+        &self,
+        value: u64,
+        operands: &[u64],
+        target: Option<u64>,
+        prefix: &mut Vec<usize>,
+        tail: &[usize],
+        search: &mut Search,
+    ) -> bool {
+        let Some((next, rest)) = operands.split_first() else {
+            if target.is_none_or(|target| target == value) {
+                search
+                    .solutions
+                    .push(prefix.iter().chain(tail).copied().collect());
+                return true;
+            }
+            return false;
+        };
+
+        let mut found = false;
+        for (index, operator) in self.operators.iter().enumerate() {
+            if let Some(value) = operator.apply(value, *next) {
+                prefix.push(index);
+                found |= self.forward_search(value, rest, target, prefix, tail, search);
+                prefix.pop();
+            }
+
+            if search.done() {
+                break;
+            }
+        }
+
+        found
+    }
+
+    /// Tries every operator sequence, evaluating each in full.
+    fn exhaustive_search(
+        &self,
+        equation: &Equation,
+        prefix: &mut Vec<usize>,
+        search: &mut Search,
+    ) -> bool {
+        if prefix.len() + 1 >= equation.operands.len() {
+            if self.evaluate(&equation.operands, prefix) == Some(equation.target) {
+                search.solutions.push(prefix.clone());
+                return true;
+            }
+            return false;
+        }
+
+        let mut found = false;
+        for index in 0..self.operators.len() {
+            prefix.push(index);
+            found |= self.exhaustive_search(equation, prefix, search);
+            prefix.pop();
+
+            if search.done() {
+                break;
+            }
+        }
+
+        found
+    }
+
+    /// Evaluates the operands with the given operators, respecting precedence.
+    fn evaluate(&self, operands: &[u64], sequence: &[usize]) -> Option<u64> {
+        let mut values = vec![*operands.first()?];
+        let mut pending: Vec<&dyn Operator> = Vec::new();
+
+        let reduce = |values: &mut Vec<u64>, operator: &dyn Operator| {
+            let right = values.pop()?;
+            let left = values.pop()?;
+            values.push(operator.apply(left, right)?);
+            Some(())
+        };
+
+        for (index, operand) in sequence.iter().zip(&operands[1..]) {
+            let operator = self.operators[*index].as_ref();
+            while let Some(top) = pending.last() {
+                if top.precedence() < operator.precedence() {
+                    break;
+                }
+                reduce(&mut values, *top)?;
+                pending.pop();
+            }
+            pending.push(operator);
+            values.push(*operand);
+        }
+
+        for operator in pending.into_iter().rev() {
+            reduce(&mut values, operator)?;
+        }
+
+        values.pop()
+    }
+}
+
+fn parse(input: &str) -> IResult<&str, Vec<Equation>> {
     separated_list1(
         line_ending,
-        separated_pair(
-            character::complete::u64,
-            tag(": "),
-            separated_list1(space1, character::complete::u64),
+        map(
+            separated_pair(
+                character::complete::u64,
+                tag(": "),
+                separated_list1(space1, character::complete::u64),
+            ),
+            |(target, operands)| Equation { target, operands },
         ),
     )(input)
 }
 
-#[derive(Copy, Clone)]
-enum Operator {
-    Add,
-    Multiply,
-    Concatenate,
-}
-
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{
+        parse_equations, part1, part2, Add, Concatenate, Equation, Evaluation, Exponent, Multiply,
+        OperatorSet, Subtract, Xor,
+    };
 
     const INPUT: &str = "190: 10 19
 3267: 81 40 27
@@ -113,6 +521,13 @@ mod tests {
 21037: 9 7 18 13
 292: 11 6 16 20";
 
+    fn equation(target: u64, operands: &[u64]) -> Equation {
+        Equation {
+            target,
+            operands: operands.to_vec(),
+        }
+    }
+
     #[test]
     fn part1_returns_total_calibration_result() -> anyhow::Result<()> {
         assert_eq!(part1(INPUT)?, 3749);
@@ -124,4 +539,76 @@ mod tests {
         assert_eq!(part2(INPUT)?, 11387);
         Ok(())
     }
+
+    #[test]
+    fn solvable_handles_long_equations() {
+        let operators = OperatorSet::new().with(Add).with(Multiply);
+        let ones = [1; 40];
+
+        assert!(operators.solvable(&equation(40, &ones)));
+        assert!(!operators.solvable(&equation(41, &ones)));
+        assert!(OperatorSet::new()
+            .with(Concatenate::DECIMAL)
+            .solvable(&equation(1_111_111_111, &ones[..10])));
+    }
+
+    #[test]
+    fn solvable_rejects_overflowing_sequences() {
+        let operators = OperatorSet::new()
+            .with(Add)
+            .with(Multiply)
+            .with(Concatenate::DECIMAL);
+
+        assert!(operators.solvable(&equation(u64::MAX, &[1_844_674_407_370_955_161, 5])));
+        assert!(!operators.solvable(&equation(5, &[u64::MAX, u64::MAX])));
+        assert!(!operators.solvable(&equation(0, &[u64::MAX, u64::MAX, 0])));
+        assert!(operators.solvable(&equation(0, &[7, 9, 0])));
+    }
+
+    #[test]
+    fn solutions_enumerates_every_operator_sequence() -> anyhow::Result<()> {
+        let operators = OperatorSet::new().with(Add).with(Multiply);
+        let equations = parse_equations(INPUT)?;
+        let solutions = operators.solutions(&equations[1]);
+
+        assert_eq!(solutions, vec![vec![0, 1], vec![1, 0]]);
+        assert_eq!(
+            operators.render(&equations[1], &solutions[0]),
+            "3267 = 81 + 40 * 27"
+        );
+        assert_eq!(operators.solutions(&equation(0, &[7, 9, 0])).len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn custom_operators_and_precedence() -> anyhow::Result<()> {
+        let operators = OperatorSet::new()
+            .with(Subtract)
+            .with(Exponent)
+            .with(Xor)
+            .with(Concatenate::new(2)?);
+        assert!(Concatenate::new(0).is_err());
+        assert!(Concatenate::new(1).is_err());
+
+        assert_eq!(
+            operators.solutions(&equation(27, &[5, 2, 3])),
+            vec![vec![0, 1]]
+        );
+        assert_eq!(
+            operators.solutions(&equation(4, &[7, 3])),
+            vec![vec![0], vec![2]]
+        );
+        assert_eq!(operators.solutions(&equation(22, &[5, 2])), vec![vec![3]]);
+
+        let precedence = OperatorSet::new()
+            .with(Add)
+            .with(Multiply)
+            .with_evaluation(Evaluation::Precedence);
+        assert_eq!(
+            precedence.solutions(&equation(14, &[2, 3, 4])),
+            vec![vec![0, 1]]
+        );
+        assert!(!precedence.solvable(&equation(20, &[2, 3, 4])));
+        Ok(())
+    }
 }
@@ -1,107 +1,293 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::anyhow;
 use itertools::Itertools;
-use std::collections::HashSet;
 
 pub fn part1(input: &str) -> usize {
-    let (map_dims, mut map) = parse(input);
-    map.sort_by(|a, b| a.frequency.cmp(&b.frequency));
-
-    let unique_antinode_locations = map
-        .into_iter()
-        .chunk_by(|antenna| antenna.frequency)
-        .into_iter()
-        .flat_map(|(_frequency, group)| {
-            let antennas = group.collect::<Vec<Antenna>>();
-            antennas
-                .iter()
-                .cartesian_product(&antennas)
-                .filter(|(a, b)| a != b)
-                .flat_map(|(a, b)| a.antinodes(b))
-                .collect::<Vec<Coordinate>>()
-        })
-        .filter(|antinode| in_bounds(antinode, &map_dims))
-        .collect::<HashSet<Coordinate>>();
-
-    unique_antinode_locations.len()
+    AntennaMap::parse(
+        input,
+        AntinodeRule::Ratio {
+            ratio: 2,
+            include_between: false,
+        },
+    )
+    .antinodes()
+    .len()
 }
 
 pub fn part2(input: &str) -> usize {
-    let (map_dims, mut map) = parse(input);
-    map.sort_by(|a, b| a.frequency.cmp(&b.frequency));
-
-    let unique_antinode_locations = map
-        .into_iter()
-        .chunk_by(|antenna| antenna.frequency)
-        .into_iter()
-        .flat_map(|(_frequency, group)| {
-            let antennas = group.collect::<Vec<Antenna>>();
-            antennas
-                .iter()
-                .cartesian_product(&antennas)
-                .filter(|(a, b)| a != b)
-                .flat_map(|(a, b)| a.resonant_antinodes(b, &map_dims))
-                .collect::<Vec<Coordinate>>()
-        })
-        .collect::<HashSet<Coordinate>>();
-
-    unique_antinode_locations.len()
+    AntennaMap::parse(input, AntinodeRule::Resonant)
+        .antinodes()
+        .len()
 }
 
-type Coordinate = [isize; 2];
+pub type Coordinate = [isize; 2];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntinodeRule {
+    /// Every grid position in line with two antennas where one antenna is exactly `ratio` times
+    /// as far away as the other. Positions between the antennas only count if `include_between`.
+    Ratio { ratio: u32, include_between: bool },
+    /// Every grid position in line with two antennas.
+    Resonant,
+}
 
 #[derive(Clone, Debug, PartialEq)]
-struct Antenna {
-    frequency: char,
-    coordinate: Coordinate,
+pub struct Antenna {
+    pub frequency: char,
+    pub coordinate: Coordinate,
 }
 
 impl Antenna {
     fn delta(&self, other: &Self) -> Coordinate {
         [
-            self.coordinate[0] - other.coordinate[0],
-            self.coordinate[1] - other.coordinate[1],
+            other.coordinate[0] - self.coordinate[0],
+            other.coordinate[1] - self.coordinate[1],
         ]
     }
 
-    fn antinodes(&self, other: &Self) -> [Coordinate; 2] {
+    /// The in-bounds antinodes of this antenna and another of the same frequency, each listed
+    /// once and the same whichever of the two antennas is `self`.
+    pub fn antinodes(
+        &self,
+        other: &Self,
+        rule: AntinodeRule,
+        map_dims: &Coordinate,
+    ) -> Vec<Coordinate> {
         let delta = self.delta(other);
-        [
-            [self.coordinate[0] + delta[0], self.coordinate[1] + delta[1]],
-            [
-                other.coordinate[0] - delta[0],
-                other.coordinate[1] - delta[1],
-            ],
-        ]
+        let start = self.coordinate;
+        let at = |multiple: isize, step: Coordinate| {
+            [start[0] + multiple * step[0], start[1] + multiple * step[1]]
+        };
+
+        let mut antinodes = Vec::new();
+        match rule {
+            AntinodeRule::Ratio {
+                ratio,
+                include_between,
+            } => {
+                let ratio = ratio as isize;
+                if ratio > 1 {
+                    if let Some(step) = divide(&delta, ratio - 1) {
+                        antinodes.push(at(-1, step));
+                        antinodes.push(at(ratio, step));
+                    }
+                }
+                if include_between && ratio > 0 {
+                    if let Some(step) = divide(&delta, ratio + 1) {
+                        antinodes.push(at(1, step));
+                        antinodes.push(at(ratio, step));
+                    }
+                }
+            }
+            AntinodeRule::Resonant => {
+                let divisor = gcd(delta[0], delta[1]);
+                if divisor == 0 {
+                    return vec![start];
+                }
+                let step = [delta[0] / divisor, delta[1] / divisor];
+                for (mut multiple, direction) in [(0, 1), (-1, -1)] {
+                    while in_bounds(&at(multiple, step), map_dims) {
+                        antinodes.push(at(multiple, step));
+                        multiple += direction;
+                    }
+                }
+            }
+        }
+
+        antinodes.retain(|antinode| in_bounds(antinode, map_dims));
+        antinodes.sort_unstable();
+        antinodes.dedup();
+        antinodes
     }
+}
 
-    fn resonant_antinodes(&self, other: &Self, map_dims: &[isize; 2]) -> Vec<Coordinate> {
-        let delta = self.delta(other);
-        let mut coordinates = Vec::new();
+fn divide(delta: &Coordinate, divisor: isize) -> Option<Coordinate> {
+    (delta[0] % divisor == 0 && delta[1] % divisor == 0)
+        .then(|| [delta[0] / divisor, delta[1] / divisor])
+}
+
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The antinodes produced by each frequency on its own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AntinodeReport {
+    pub antinodes_by_frequency: BTreeMap<char, HashSet<Coordinate>>,
+}
+
+impl AntinodeReport {
+    pub fn all_antinodes(&self) -> HashSet<Coordinate> {
+        self.antinodes_by_frequency
+            .values()
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// The antinodes shared by each pair of frequencies that share any.
+    pub fn overlaps(&self) -> BTreeMap<(char, char), HashSet<Coordinate>> {
+        self.antinodes_by_frequency
+            .iter()
+            .tuple_combinations()
+            .filter_map(|((a, a_antinodes), (b, b_antinodes))| {
+                let shared = a_antinodes
+                    .intersection(b_antinodes)
+                    .copied()
+                    .collect::<HashSet<Coordinate>>();
+                (!shared.is_empty()).then_some(((*a, *b), shared))
+            })
+            .collect()
+    }
+}
+
+/// A map of antennas that keeps its antinodes up to date as antennas are added and removed.
+///
+/// Each antinode is counted once per antenna pair that produces it, so an edit only revisits
+/// the pairs involving the antenna that changed.
+#[derive(Clone, Debug)]
+pub struct AntennaMap {
+    map_dims: Coordinate,
+    rule: AntinodeRule,
+    antennas: BTreeMap<Coordinate, char>,
+    antinode_counts: BTreeMap<char, HashMap<Coordinate, usize>>,
+}
+
+impl AntennaMap {
+    pub fn parse(input: &str, rule: AntinodeRule) -> Self {
+        let (map_dims, antennas) = parse(input);
+        let mut map = AntennaMap {
+            map_dims,
+            rule,
+            antennas: BTreeMap::new(),
+            antinode_counts: BTreeMap::new(),
+        };
+        for antenna in antennas {
+            map.insert(antenna);
+        }
+
+        map
+    }
+
+    pub fn antennas(&self) -> impl Iterator<Item = Antenna> + '_ {
+        self.antennas.iter().map(|(coordinate, frequency)| Antenna {
+            frequency: *frequency,
+            coordinate: *coordinate,
+        })
+    }
+
+    pub fn add_antenna(&mut self, antenna: Antenna) -> anyhow::Result<()> {
+        if !in_bounds(&antenna.coordinate, &self.map_dims) {
+            return Err(anyhow!("{:?} is outside the map", antenna.coordinate));
+        }
+        if let Some(frequency) = self.antennas.get(&antenna.coordinate) {
+            return Err(anyhow!(
+                "{:?} already holds an antenna of frequency {frequency}",
+                antenna.coordinate
+            ));
+        }
 
-        let mut current = self.coordinate;
-        loop {
-            coordinates.push(current);
-            current = [current[0] + delta[0], current[1] + delta[1]];
+        self.insert(antenna);
+        Ok(())
+    }
 
-            if !in_bounds(&current, map_dims) {
-                break;
+    pub fn remove_antenna(&mut self, coordinate: &Coordinate) -> Option<Antenna> {
+        let frequency = self.antennas.remove(coordinate)?;
+        let antenna = Antenna {
+            frequency,
+            coordinate: *coordinate,
+        };
+        let counts = self.antinode_counts.entry(frequency).or_default();
+        for other in partners(&self.antennas, &antenna) {
+            for antinode in antenna.antinodes(&other, self.rule, &self.map_dims) {
+                if let Some(count) = counts.get_mut(&antinode) {
+                    *count -= 1;
+                    if *count == 0 {
+                        counts.remove(&antinode);
+                    }
+                }
             }
         }
+        if counts.is_empty() {
+            self.antinode_counts.remove(&frequency);
+        }
 
-        current = other.coordinate;
-        loop {
-            coordinates.push(current);
-            current = [current[0] - delta[0], current[1] - delta[1]];
+        Some(antenna)
+    }
 
-            if !in_bounds(&current, map_dims) {
-                break;
+    fn insert(&mut self, antenna: Antenna) {
+        let counts = self.antinode_counts.entry(antenna.frequency).or_default();
+        for other in partners(&self.antennas, &antenna) {
+            for antinode in antenna.antinodes(&other, self.rule, &self.map_dims) {
+                *counts.entry(antinode).or_default() += 1;
             }
         }
+        self.antennas.insert(antenna.coordinate, antenna.frequency);
+    }
 
-        coordinates
+    pub fn antinodes(&self) -> HashSet<Coordinate> {
+        self.antinode_counts
+            .values()
+            .flat_map(|counts| counts.keys())
+            .copied()
+            .collect()
+    }
+
+    pub fn report(&self) -> AntinodeReport {
+        AntinodeReport {
+            antinodes_by_frequency: self
+                .antinode_counts
+                .iter()
+                .map(|(frequency, counts)| (*frequency, counts.keys().copied().collect()))
+                .collect(),
+        }
     }
 }
 
-fn in_bounds(coordinate: &Coordinate, map_dims: &[isize; 2]) -> bool {
+/// The other antennas on the same frequency as `antenna`.
+fn partners<'a>(
+    antennas: &'a BTreeMap<Coordinate, char>,
+    antenna: &'a Antenna,
+) -> impl Iterator<Item = Antenna> + 'a {
+    antennas
+        .iter()
+        .filter(|(coordinate, frequency)| {
+            **frequency == antenna.frequency && **coordinate != antenna.coordinate
+        })
+        .map(|(coordinate, frequency)| Antenna {
+            frequency: *frequency,
+            coordinate: *coordinate,
+        })
+}
+
+/// Draws the map as in the puzzle, with `#` marking antinodes not covered by an antenna.
+impl std::fmt::Display for AntennaMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let antinodes = self.antinodes();
+        for row in 0..self.map_dims[0] {
+            for col in 0..self.map_dims[1] {
+                let tile = match self.antennas.get(&[row, col]) {
+                    Some(frequency) => *frequency,
+                    None if antinodes.contains(&[row, col]) => '#',
+                    None => '.',
+                };
+                write!(f, "{tile}")?;
+            }
+            if row + 1 < self.map_dims[0] {
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn in_bounds(coordinate: &Coordinate, map_dims: &Coordinate) -> bool {
     coordinate[0] >= 0
         && coordinate[0] < map_dims[0]
         && coordinate[1] >= 0
@@ -133,7 +319,7 @@ fn parse(input: &str) -> (Coordinate, Vec<Antenna>) {
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{part1, part2, Antenna, AntennaMap, AntinodeRule};
 
     const INPUT: &str = "............
 ........0...
@@ -157,4 +343,116 @@ mod tests {
     fn part2_returns_number_of_unique_antinode_positions_with_resonance() {
         assert_eq!(part2(INPUT), 34);
     }
+
+    #[test]
+    fn antinodes_include_points_between_antennas_and_reduced_steps() {
+        let input = "a.....\n......\n......\n...a..\n......\n......";
+        let between = AntennaMap::parse(
+            input,
+            AntinodeRule::Ratio {
+                ratio: 2,
+                include_between: true,
+            },
+        )
+        .report();
+        let beyond = AntennaMap::parse(
+            input,
+            AntinodeRule::Ratio {
+                ratio: 2,
+                include_between: false,
+            },
+        );
+        let resonant = AntennaMap::parse(input, AntinodeRule::Resonant);
+
+        assert_eq!(
+            between.antinodes_by_frequency[&'a'],
+            [[1, 1], [2, 2]].into_iter().collect()
+        );
+        assert!(beyond.antinodes().is_empty());
+        assert_eq!(part1(input), 0);
+        assert_eq!(resonant.antinodes().len(), 6);
+    }
+
+    #[test]
+    fn report_lists_overlapping_frequencies() {
+        let separate = AntennaMap::parse("a.b\n...\na.b", AntinodeRule::Resonant).report();
+
+        assert_eq!(separate.antinodes_by_frequency.len(), 2);
+        assert!(separate.overlaps().is_empty());
+
+        let overlaps = AntennaMap::parse("a..\n.b.\nb.a", AntinodeRule::Resonant)
+            .report()
+            .overlaps();
+        assert_eq!(overlaps[&('a', 'b')], [[1, 1]].into_iter().collect());
+    }
+
+    #[test]
+    fn render_overlays_antinodes() {
+        let map = AntennaMap::parse(
+            INPUT,
+            AntinodeRule::Ratio {
+                ratio: 2,
+                include_between: false,
+            },
+        );
+
+        assert_eq!(
+            map.to_string(),
+            "......#....#
+...#....0...
+....#0....#.
+..#....0....
+....0....#..
+.#....A.....
+...#........
+#......#....
+........A...
+.........A..
+..........#.
+..........#."
+        );
+    }
+
+    #[test]
+    fn edits_update_antinodes() -> anyhow::Result<()> {
+        let mut map = AntennaMap::parse(INPUT, AntinodeRule::Resonant);
+        let removed = map.remove_antenna(&[1, 8]);
+
+        assert_eq!(
+            removed,
+            Some(Antenna {
+                frequency: '0',
+                coordinate: [1, 8],
+            })
+        );
+        assert_eq!(
+            map.antinodes(),
+            AntennaMap::parse(&INPUT.replacen('0', ".", 1), AntinodeRule::Resonant).antinodes()
+        );
+
+        map.add_antenna(Antenna {
+            frequency: '0',
+            coordinate: [1, 8],
+        })?;
+        assert_eq!(map.antinodes().len(), 34);
+
+        // The only antinodes left after removing either antenna came from their pair alone.
+        let input = "...\na..\na..";
+        for (removed, remaining) in [([1, 0], "...\n...\na.."), ([2, 0], "...\na..\n...")] {
+            let mut map = AntennaMap::parse(input, AntinodeRule::Resonant);
+            map.remove_antenna(&removed);
+            assert!(map.antinodes().is_empty());
+            assert_eq!(
+                map.antinodes(),
+                AntennaMap::parse(remaining, AntinodeRule::Resonant).antinodes()
+            );
+        }
+        assert!(map
+            .add_antenna(Antenna {
+                frequency: 'b',
+                coordinate: [1, 8],
+            })
+            .is_err());
+        Ok(())
+    }
 }
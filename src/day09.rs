@@ -1,128 +1,256 @@
-use std::{cmp::Ordering, collections::VecDeque, iter::zip};
+use std::{cmp::Reverse, collections::BinaryHeap};
 
-pub fn part1(input: &str) -> u64 {
-    let mut fragmented = parse(input);
-    let mut compressed = VecDeque::default();
+use anyhow::anyhow;
 
-    while let Some(front) = fragmented.pop_front() {
-        let free_start = front.end;
-        let free_len = fragmented
-            .front()
-            .map_or(0, |second| second.start - front.end);
+pub fn part1(input: &str) -> anyhow::Result<u64> {
+    Ok(DiskMap::parse(input)?.compact(Strategy::Blocks).checksum())
+}
 
-        compressed.push_back(front);
-        fill_free_block(&mut fragmented, &mut compressed, free_start, free_len);
-    }
+pub fn part2(input: &str) -> anyhow::Result<u64> {
+    Ok(DiskMap::parse(input)?
+        .compact(Strategy::FirstFit)
+        .checksum())
+}
 
-    checksum(&compressed)
+/// How files are moved towards the start of the disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Moves blocks one at a time from the end of the disk into the leftmost free block,
+    /// splitting files as needed.
+    Blocks,
+    /// Moves each file, rightmost first, into the leftmost earlier gap that holds all of it.
+    FirstFit,
+    /// Moves each file, rightmost first, into the smallest earlier gap that holds all of it,
+    /// preferring the leftmost of equally sized gaps.
+    BestFit,
 }
 
-fn fill_free_block(
-    fragmented: &mut VecDeque<File>,
-    compressed: &mut VecDeque<File>,
-    mut free_start: u64,
-    mut free_len: u64,
-) {
-    if free_len == 0 {
-        return;
-    }
-
-    while let Some(back) = fragmented.pop_back() {
-        match back.len().cmp(&free_len) {
-            Ordering::Greater => {
-                let moved = File {
-                    start: free_start,
-                    end: free_start + free_len,
-                    id: back.id,
-                    fixed: false,
-                };
-                let leftover = File {
-                    start: back.start,
-                    end: back.end - free_len,
-                    id: back.id,
-                    fixed: false,
-                };
-                compressed.push_back(moved);
-                fragmented.push_back(leftover);
-                return;
-            }
-            Ordering::Equal => {
-                compressed.push_back(File {
-                    start: free_start,
-                    end: free_start + free_len,
-                    id: back.id,
-                    fixed: false,
-                });
-                return;
-            }
-            Ordering::Less => {
-                compressed.push_back(File {
-                    start: free_start,
-                    end: free_start + back.len(),
-                    id: back.id,
-                    fixed: false,
+/// The layout of files on a disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiskMap {
+    /// File extents sorted by start position. A file split by compaction has several extents.
+    files: Vec<File>,
+    len: u64,
+}
+
+/// Summary of the free space between files. Free space after the last file is reported
+/// separately in `trailing_free_blocks` and is not counted in `gaps`, `free_blocks` or
+/// `largest_gap`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub gaps: usize,
+    pub free_blocks: u64,
+    pub largest_gap: u64,
+    pub trailing_free_blocks: u64,
+}
+
+impl DiskMap {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut files = Vec::default();
+        let mut disk_index = 0;
+
+        for (index, number) in input.trim_end().char_indices() {
+            let len: u64 = number
+                .to_digit(10)
+                .ok_or_else(|| anyhow!("Invalid length {number:?} at index {index}"))?
+                .into();
+            if index % 2 == 0 && len > 0 {
+                files.push(File {
+                    start: disk_index,
+                    end: disk_index + len,
+                    id: (index as u64) / 2,
                 });
-                free_len -= back.len();
-                free_start += back.len();
             }
+            disk_index += len;
+        }
+
+        Ok(DiskMap {
+            files,
+            len: disk_index,
+        })
+    }
+
+    pub fn compact(&self, strategy: Strategy) -> DiskMap {
+        let files = self.files.clone();
+        let mut free_spans = self.free_spans();
+        let mut files = match strategy {
+            Strategy::Blocks => compact_blocks(files, &mut free_spans),
+            Strategy::FirstFit => compact_files(files, &mut free_spans, FreeSpans::pop_leftmost),
+            Strategy::BestFit => compact_files(files, &mut free_spans, FreeSpans::pop_smallest),
+        };
+        files.sort_unstable_by_key(|file| file.start);
+
+        DiskMap {
+            files,
+            len: self.len,
+        }
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|file| file.id * file.len() * (2 * file.start + file.len() - 1) / 2)
+            .sum()
+    }
+
+    pub fn fragmentation(&self) -> Fragmentation {
+        let mut fragmentation = Fragmentation::default();
+        for span in self.gaps() {
+            fragmentation.gaps += 1;
+            fragmentation.free_blocks += span.len;
+            fragmentation.largest_gap = fragmentation.largest_gap.max(span.len);
+        }
+        fragmentation.trailing_free_blocks =
+            self.len - self.files.last().map_or(0, |file| file.end);
+
+        fragmentation
+    }
+
+    fn gaps(&self) -> impl Iterator<Item = Span> + '_ {
+        let ends = std::iter::once(0).chain(self.files.iter().map(|file| file.end));
+        ends.zip(self.files.iter())
+            .filter(|(end, file)| file.start > *end)
+            .map(|(end, file)| Span {
+                start: end,
+                len: file.start - end,
+            })
+    }
+
+    fn free_spans(&self) -> FreeSpans {
+        let mut free_spans = FreeSpans::default();
+        for span in self.gaps() {
+            free_spans.push(span.start, span.len);
         }
+
+        free_spans
     }
 }
 
-pub fn part2(input: &str) -> u64 {
-    let mut disk_map = parse(input);
-    let mut back_index = disk_map.len() - 1;
-
-    while back_index > 0 {
-        let File {
-            start,
-            end,
-            id,
-            fixed,
-        } = &disk_map[back_index];
-        if *fixed {
-            back_index -= 1;
-            continue;
+/// Renders one character per block: `.` for free blocks and the file id otherwise. Ids beyond 9
+/// continue through `a`–`z` and then wrap around.
+impl std::fmt::Display for DiskMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut position = 0;
+        for file in &self.files {
+            let digit = char::from_digit((file.id % 36) as u32, 36).unwrap_or('?');
+            write!(f, "{}", ".".repeat((file.start - position) as usize))?;
+            write!(f, "{}", String::from(digit).repeat(file.len() as usize))?;
+            position = file.end;
         }
+        write!(f, "{}", ".".repeat((self.len - position) as usize))
+    }
+}
+
+/// Moves blocks one at a time from the end of the disk into the leftmost free span.
+fn compact_blocks(mut files: Vec<File>, free_spans: &mut FreeSpans) -> Vec<File> {
+    let mut compacted = Vec::with_capacity(files.len());
 
-        let move_len = end - start;
-        let mut moved_file = false;
-
-        for (first_index, second_index) in zip(0..back_index, 1..=back_index) {
-            let first = &disk_map[first_index];
-            let second = &disk_map[second_index];
-            let free_len = second.start - first.end;
-
-            if free_len >= move_len {
-                disk_map.insert(
-                    second_index,
-                    File {
-                        start: first.end,
-                        end: first.end + move_len,
-                        id: *id,
-                        fixed: true,
-                    },
-                );
-                disk_map.remove(back_index + 1);
-                moved_file = true;
+    while let Some(mut file) = files.pop() {
+        while file.len() > 0 {
+            let Some(span) = free_spans.pop_leftmost(1, file.start) else {
                 break;
-            }
+            };
+            let moved_len = span.len.min(file.len());
+            compacted.push(File {
+                start: span.start,
+                end: span.start + moved_len,
+                id: file.id,
+            });
+            free_spans.push(span.start + moved_len, span.len - moved_len);
+            file.end -= moved_len;
+        }
+
+        if file.len() > 0 {
+            // Nothing to the left of this file is free, so nothing before it can move either.
+            compacted.push(file);
+            compacted.append(&mut files);
+        }
+    }
+
+    compacted
+}
+
+/// Moves each file, rightmost first, into the span removed by `take_span`, which is given the
+/// file's length and start position.
+fn compact_files(
+    mut files: Vec<File>,
+    free_spans: &mut FreeSpans,
+    take_span: fn(&mut FreeSpans, u64, u64) -> Option<Span>,
+) -> Vec<File> {
+    for file in files.iter_mut().rev() {
+        let len = file.len();
+        if let Some(span) = take_span(free_spans, len, file.start) {
+            free_spans.push(span.start + len, span.len - len);
+            file.start = span.start;
+            file.end = span.start + len;
         }
+    }
+
+    files
+}
 
-        if !moved_file {
-            disk_map[back_index].fixed = true;
-            back_index -= 1;
+const MAX_SPAN_LEN: usize = 9;
+
+/// Free spans bucketed by length, each bucket a min-heap of start positions. The last bucket
+/// holds every span of at least `MAX_SPAN_LEN` blocks.
+///
+/// Finding the leftmost span of at least some length only has to inspect the top of at most
+/// nine heaps, and filling part of a span pushes its remainder into a shorter bucket.
+#[derive(Default)]
+struct FreeSpans {
+    by_len: [BinaryHeap<Reverse<(u64, u64)>>; MAX_SPAN_LEN + 1],
+}
+
+#[derive(Clone, Copy)]
+struct Span {
+    start: u64,
+    len: u64,
+}
+
+impl FreeSpans {
+    fn push(&mut self, start: u64, len: u64) {
+        if len > 0 {
+            self.by_len[(len as usize).min(MAX_SPAN_LEN)].push(Reverse((start, len)));
         }
     }
 
-    checksum(&disk_map)
+    /// The buckets that can hold `min_len` blocks, with the leftmost span of each that starts
+    /// before `before`.
+    fn candidates(&self, min_len: u64, before: u64) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let first_bucket = (min_len.max(1) as usize).min(MAX_SPAN_LEN);
+        (first_bucket..=MAX_SPAN_LEN).filter_map(move |bucket| {
+            self.by_len[bucket]
+                .peek()
+                .filter(|Reverse((start, len))| *start < before && *len >= min_len)
+                .map(|Reverse((start, _))| (bucket, *start))
+        })
+    }
+
+    /// Removes the leftmost span starting before `before` that is at least `min_len` long.
+    fn pop_leftmost(&mut self, min_len: u64, before: u64) -> Option<Span> {
+        let (bucket, _) = self
+            .candidates(min_len, before)
+            .min_by_key(|(_, start)| *start)?;
+        self.pop(bucket)
+    }
+
+    /// Removes the shortest span starting before `before` that is at least `min_len` long.
+    fn pop_smallest(&mut self, min_len: u64, before: u64) -> Option<Span> {
+        let (bucket, _) = self.candidates(min_len, before).next()?;
+        self.pop(bucket)
+    }
+
+    fn pop(&mut self, bucket: usize) -> Option<Span> {
+        let Reverse((start, len)) = self.by_len[bucket].pop()?;
+        Some(Span { start, len })
+    }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct File {
     start: u64,
     end: u64,
     id: u64,
-    fixed: bool,
 }
 
 impl File {
@@ -131,48 +259,74 @@ impl File {
     }
 }
 
-fn parse(input: &str) -> VecDeque<File> {
-    let mut disk_map = VecDeque::default();
-    let mut disk_index = 0;
-    let mut file = true;
-
-    for (index, number) in input.char_indices() {
-        let len: u64 = number.to_digit(10).unwrap().into();
-        if file {
-            disk_map.push_back(File {
-                start: disk_index,
-                end: disk_index + len,
-                id: (index as u64) / 2,
-                fixed: false,
-            });
-        }
-        file = !file;
-        disk_index += len;
-    }
-
-    disk_map
-}
-
-fn checksum(disk_map: &VecDeque<File>) -> u64 {
-    disk_map
-        .iter()
-        .map(|file| file.id * file.len() * (2 * file.start + file.len() - 1) / 2)
-        .sum()
-}
-
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{part1, part2, DiskMap, Fragmentation, Strategy};
 
     const INPUT: &str = "2333133121414131402";
 
     #[test]
-    pub fn part1_checksums_maximal_compaction() {
-        assert_eq!(part1(INPUT), 1928);
+    pub fn part1_checksums_maximal_compaction() -> anyhow::Result<()> {
+        assert_eq!(part1(INPUT)?, 1928);
+        Ok(())
+    }
+
+    #[test]
+    pub fn part2_checksums_compaction_without_fragmentation() -> anyhow::Result<()> {
+        assert_eq!(part2(INPUT)?, 2858);
+        Ok(())
     }
 
     #[test]
-    pub fn part2_checksums_compaction_without_fragmentation() {
-        assert_eq!(part2(INPUT), 2858);
+    pub fn files_only_move_into_earlier_spans() -> anyhow::Result<()> {
+        // 0..111....22222 compacts to 022111222 block by block, but no whole file fits earlier.
+        assert_eq!(part1("12345")?, 60);
+        assert_eq!(part2("12345")?, 132);
+        Ok(())
+    }
+
+    #[test]
+    pub fn strategies_render_resulting_layouts() -> anyhow::Result<()> {
+        let disk_map = DiskMap::parse(INPUT)?;
+
+        assert_eq!(
+            disk_map.to_string(),
+            "00...111...2...333.44.5555.6666.777.888899"
+        );
+        assert_eq!(
+            disk_map.compact(Strategy::Blocks).to_string(),
+            "0099811188827773336446555566.............."
+        );
+        assert_eq!(
+            disk_map.compact(Strategy::FirstFit).to_string(),
+            "00992111777.44.333....5555.6666.....8888.."
+        );
+        let disk_map = DiskMap::parse("1332124")?;
+        assert_eq!(
+            disk_map.compact(Strategy::FirstFit).to_string(),
+            "02..111.....3333"
+        );
+        assert_eq!(
+            disk_map.compact(Strategy::BestFit).to_string(),
+            "0111...2....3333"
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn fragmentation_counts_gaps_between_files() -> anyhow::Result<()> {
+        let compacted = DiskMap::parse(INPUT)?.compact(Strategy::FirstFit);
+
+        assert_eq!(
+            compacted.fragmentation(),
+            Fragmentation {
+                gaps: 5,
+                free_blocks: 12,
+                largest_gap: 5,
+                trailing_free_blocks: 2,
+            }
+        );
+        assert!(DiskMap::parse("12a").is_err());
+        Ok(())
     }
 }
@@ -1,170 +1,322 @@
-use std::collections::{HashSet, VecDeque};
+use std::ops::RangeInclusive;
 
 use anyhow::anyhow;
 use itertools::Itertools;
 use ndarray::Array2;
 
 pub fn part1(input: &str) -> anyhow::Result<usize> {
-    let topographical_map = parse(input)?;
+    let map = TopographicMap::parse(input)?;
+    Ok(map.survey(&TrailRules::default())?.score)
+}
 
-    let total_trail_score = topographical_map
-        .indexed_iter()
-        .filter_map(|(position, height)| {
-            if *height != 0 {
-                return None;
-            }
+pub fn part2(input: &str) -> anyhow::Result<usize> {
+    let map = TopographicMap::parse(input)?;
+    Ok(map.survey(&TrailRules::default())?.rating)
+}
 
-            Some(score_trailhead(&topographical_map, position))
-        })
-        .sum();
+pub type Position = (usize, usize);
 
-    Ok(total_trail_score)
+/// Which paths across a map count as hiking trails.
+///
+/// A trail starts on a cell at the start height, ends on a cell at the end height, and changes
+/// height by an allowed step at every move. Steps must all lead towards the end height.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrailRules {
+    start_height: u32,
+    end_height: u32,
+    steps: RangeInclusive<i64>,
+    diagonal_moves: bool,
 }
 
-pub fn part2(input: &str) -> anyhow::Result<usize> {
-    let topographical_map = parse(input)?;
+impl Default for TrailRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    let total_trail_score = topographical_map
-        .indexed_iter()
-        .filter_map(|(position, height)| {
-            if *height != 0 {
-                return None;
-            }
+impl TrailRules {
+    /// Trails climb from 0 to 9 one step at a time, moving only horizontally or vertically.
+    pub fn new() -> Self {
+        TrailRules {
+            start_height: 0,
+            end_height: 9,
+            steps: 1..=1,
+            diagonal_moves: false,
+        }
+    }
 
-            Some(rate_trailhead(&topographical_map, position))
-        })
-        .sum();
+    pub fn with_heights(mut self, start_height: u32, end_height: u32) -> Self {
+        self.start_height = start_height;
+        self.end_height = end_height;
+        self
+    }
+
+    /// Height changes allowed per move; negative steps descend.
+    pub fn with_steps(mut self, steps: RangeInclusive<i64>) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    pub fn with_diagonal_moves(mut self) -> Self {
+        self.diagonal_moves = true;
+        self
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let direction = i64::from(self.end_height).cmp(&i64::from(self.start_height));
+        if self.steps.is_empty()
+            || self.steps.contains(&0)
+            || (direction.is_ne() && self.steps.start().cmp(&0) != direction)
+        {
+            return Err(anyhow!(
+                "Steps {:?} do not all lead from height {} to {}",
+                self.steps,
+                self.start_height,
+                self.end_height
+            ));
+        }
 
-    Ok(total_trail_score)
+        Ok(())
+    }
+
+    /// How many height levels a cell at `height` is from the end of a trail, if it can be on one.
+    fn distance(&self, height: u32) -> Option<usize> {
+        let (low, high) = if self.start_height <= self.end_height {
+            (self.start_height, self.end_height)
+        } else {
+            (self.end_height, self.start_height)
+        };
+        (low..=high)
+            .contains(&height)
+            .then(|| height.abs_diff(self.end_height) as usize)
+    }
+
+    fn allows(&self, from: u32, to: u32) -> bool {
+        self.steps.contains(&(i64::from(to) - i64::from(from)))
+    }
 }
 
-#[derive(Debug)]
-struct SearchState {
-    position: (usize, usize),
-    height: u32,
+/// Summed scores and ratings of every trailhead on a map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Survey {
+    /// Number of distinct (trailhead, trail end) pairs connected by a trail.
+    pub score: usize,
+    /// Number of distinct trails.
+    pub rating: usize,
 }
 
-fn score_trailhead(topographical_map: &ndarray::Array2<u32>, start: (usize, usize)) -> usize {
-    let mut queue = VecDeque::default();
-    queue.push_back(SearchState {
-        position: start,
-        height: topographical_map[start],
-    });
-    let mut trail_ends = HashSet::<(usize, usize)>::default();
-
-    while let Some(state) = queue.pop_front() {
-        if state.height == 9 {
-            trail_ends.insert(state.position);
-        }
-        let (row, col) = state.position;
-        let next_height = state.height + 1;
-
-        if row > 0 && topographical_map[(row - 1, col)] == next_height {
-            queue.push_back(SearchState {
-                position: (row - 1, col),
-                height: next_height,
-            });
-        }
+/// A topographic map where `.` marks impassable cells.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopographicMap {
+    heights: Array2<Option<u32>>,
+}
+
+/// Per-cell trail counts from a sweep, plus the trail ends reachable from each trailhead.
+struct Sweep {
+    ratings: Array2<usize>,
+    trail_ends: Array2<Vec<u64>>,
+    trailheads: Vec<Position>,
+}
+
+impl TopographicMap {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let raw: Vec<Vec<Option<u32>>> = input
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .map(|height| match height {
+                        '.' => Ok(None),
+                        _ => height.to_digit(10).map(Some).ok_or(anyhow!(
+                            "Unable to parse character into base 10 digit: {height}"
+                        )),
+                    })
+                    .try_collect()
+            })
+            .try_collect()?;
+
+        let num_rows = raw.len();
+        let num_cols = raw.first().map_or(0, Vec::len);
+        let heights =
+            Array2::from_shape_vec((num_rows, num_cols), raw.into_iter().flatten().collect())
+                .map_err(|e| anyhow!("{}", e))?;
+
+        Ok(TopographicMap { heights })
+    }
+
+    pub fn survey(&self, rules: &TrailRules) -> anyhow::Result<Survey> {
+        let sweep = self.sweep(rules)?;
+        Ok(sweep.trailheads.iter().fold(
+            Survey {
+                score: 0,
+                rating: 0,
+            },
+            |survey, trailhead| Survey {
+                score: survey.score
+                    + sweep.trail_ends[*trailhead]
+                        .iter()
+                        .map(|word| word.count_ones() as usize)
+                        .sum::<usize>(),
+                rating: survey.rating + sweep.ratings[*trailhead],
+            },
+        ))
+    }
 
-        if row < topographical_map.dim().0 - 1 && topographical_map[(row + 1, col)] == next_height {
-            queue.push_back(SearchState {
-                position: (row + 1, col),
-                height: next_height,
-            });
+    /// Lists up to `limit` trails from `trailhead`, each as the sequence of positions it visits.
+    pub fn trails(
+        &self,
+        rules: &TrailRules,
+        trailhead: Position,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Vec<Position>>> {
+        let sweep = self.sweep(rules)?;
+        let mut trails = vec![];
+        if sweep
+            .ratings
+            .get(trailhead)
+            .is_some_and(|rating| *rating > 0)
+            && self.heights[trailhead] == Some(rules.start_height)
+        {
+            self.follow(
+                rules,
+                &sweep.ratings,
+                &mut vec![trailhead],
+                &mut trails,
+                limit,
+            );
         }
 
-        if col > 0 && topographical_map[(row, col - 1)] == next_height {
-            queue.push_back(SearchState {
-                position: (row, col - 1),
-                height: next_height,
-            });
+        Ok(trails)
+    }
+
+    fn follow(
+        &self,
+        rules: &TrailRules,
+        ratings: &Array2<usize>,
+        path: &mut Vec<Position>,
+        trails: &mut Vec<Vec<Position>>,
+        limit: usize,
+    ) {
+        let position = *path.last().unwrap();
+        let Some(height) = self.heights[position] else {
+            return;
+        };
+        if height == rules.end_height {
+            trails.push(path.clone());
+            return;
         }
 
-        if col < topographical_map.dim().1 - 1 && topographical_map[(row, col + 1)] == next_height {
-            queue.push_back(SearchState {
-                position: (row, col + 1),
-                height: next_height,
-            });
+        for next in self.neighbors(position, rules.diagonal_moves) {
+            if trails.len() >= limit {
+                return;
+            }
+            if ratings[next] == 0 || !self.heights[next].is_some_and(|to| rules.allows(height, to))
+            {
+                continue;
+            }
+            path.push(next);
+            self.follow(rules, ratings, path, trails, limit);
+            path.pop();
         }
     }
 
-    trail_ends.len()
-}
+    /// Counts trails from every cell in one pass, starting at the trail ends and working back.
+    ///
+    /// Each cell's rating is the sum of its successors' ratings, and its set of reachable trail
+    /// ends is the union of theirs. A cell's successors are at most the largest step closer to
+    /// the end, so only that many levels of trail end sets are kept around.
+    fn sweep(&self, rules: &TrailRules) -> anyhow::Result<Sweep> {
+        rules.validate()?;
 
-fn rate_trailhead(topographical_map: &ndarray::Array2<u32>, start: (usize, usize)) -> usize {
-    let mut queue = VecDeque::default();
-    queue.push_back(SearchState {
-        position: start,
-        height: topographical_map[start],
-    });
-    let mut rating = 0;
-
-    while let Some(state) = queue.pop_front() {
-        if state.height == 9 {
-            rating += 1;
-        }
-        let (row, col) = state.position;
-        let next_height = state.height + 1;
-
-        if row > 0 && topographical_map[(row - 1, col)] == next_height {
-            queue.push_back(SearchState {
-                position: (row - 1, col),
-                height: next_height,
-            });
+        let num_levels = rules.start_height.abs_diff(rules.end_height) as usize + 1;
+        let mut levels = vec![vec![]; num_levels];
+        for (position, height) in self.heights.indexed_iter() {
+            if let Some(distance) = height.and_then(|height| rules.distance(height)) {
+                levels[distance].push(position);
+            }
         }
 
-        if row < topographical_map.dim().0 - 1 && topographical_map[(row + 1, col)] == next_height {
-            queue.push_back(SearchState {
-                position: (row + 1, col),
-                height: next_height,
-            });
+        let max_step = rules
+            .steps
+            .start()
+            .unsigned_abs()
+            .max(rules.steps.end().unsigned_abs());
+        let num_words = levels[0].len().div_ceil(64);
+        let mut ratings = Array2::<usize>::zeros(self.heights.dim());
+        let mut trail_ends = Array2::<Vec<u64>>::default(self.heights.dim());
+        for (index, position) in levels[0].iter().enumerate() {
+            ratings[*position] = 1;
+            trail_ends[*position] = vec![0; num_words];
+            trail_ends[*position][index / 64] |= 1 << (index % 64);
         }
 
-        if col > 0 && topographical_map[(row, col - 1)] == next_height {
-            queue.push_back(SearchState {
-                position: (row, col - 1),
-                height: next_height,
-            });
-        }
+        for distance in 1..num_levels {
+            for position in &levels[distance] {
+                let height = self.heights[*position].unwrap_or_default();
+                let mut rating = 0usize;
+                let mut reachable = vec![0; num_words];
+                for next in self.neighbors(*position, rules.diagonal_moves) {
+                    if !self.heights[next].is_some_and(|to| rules.allows(height, to)) {
+                        continue;
+                    }
+                    rating = rating
+                        .checked_add(ratings[next])
+                        .ok_or(anyhow!("Too many trails through {position:?}"))?;
+                    for (word, next_word) in reachable.iter_mut().zip(&trail_ends[next]) {
+                        *word |= next_word;
+                    }
+                }
+                ratings[*position] = rating;
+                trail_ends[*position] = reachable;
+            }
 
-        if col < topographical_map.dim().1 - 1 && topographical_map[(row, col + 1)] == next_height {
-            queue.push_back(SearchState {
-                position: (row, col + 1),
-                height: next_height,
-            });
+            if let Some(stale) = distance.checked_sub(max_step as usize) {
+                for position in &levels[stale] {
+                    trail_ends[*position] = Vec::new();
+                }
+            }
         }
-    }
 
-    rating
-}
+        Ok(Sweep {
+            ratings,
+            trail_ends,
+            trailheads: levels.pop().unwrap_or_default(),
+        })
+    }
 
-fn parse(input: &str) -> anyhow::Result<Array2<u32>> {
-    let raw: Vec<Vec<u32>> = input
-        .lines()
-        .map(|line| {
-            line.chars()
-                .map(|height| {
-                    height.to_digit(10).ok_or(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        anyhow!("Unable to parse character into base 10 digit: {height}"),
-                    ))
-                })
-                .try_collect()
+    fn neighbors(
+        &self,
+        (row, col): Position,
+        diagonal_moves: bool,
+    ) -> impl Iterator<Item = Position> + '_ {
+        let offsets: &[(isize, isize)] = if diagonal_moves {
+            &ALL_OFFSETS
+        } else {
+            &ALL_OFFSETS[..4]
+        };
+        offsets.iter().filter_map(move |(row_offset, col_offset)| {
+            let next = (
+                row.checked_add_signed(*row_offset)?,
+                col.checked_add_signed(*col_offset)?,
+            );
+            self.heights.get(next).map(|_| next)
         })
-        .try_collect()?;
-
-    let num_rows = raw.len();
-    let num_cols = raw[0].len();
-    Array2::from_shape_vec(
-        (num_rows, num_cols),
-        raw.iter().flatten().cloned().collect(),
-    )
-    .map_err(|e| anyhow!("{}", e))
+    }
 }
 
+const ALL_OFFSETS: [(isize, isize); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{part1, part2, Survey, TopographicMap, TrailRules};
 
     const INPUT: &str = "89010123
 78121874
@@ -186,4 +338,83 @@ mod tests {
         assert_eq!(part2(INPUT)?, 81);
         Ok(())
     }
+
+    #[test]
+    pub fn survey_counts_trails_through_shared_cells() -> anyhow::Result<()> {
+        // Every monotone lattice path from the corner is a trail to the single summit.
+        let map = TopographicMap::parse("012345\n123456\n234567\n345678\n456789")?;
+        assert_eq!(
+            map.survey(&TrailRules::new())?,
+            Survey {
+                score: 1,
+                rating: 126
+            }
+        );
+
+        let map = TopographicMap::parse("0123456789\n1234567898")?;
+        assert_eq!(
+            map.survey(&TrailRules::new())?,
+            Survey {
+                score: 2,
+                rating: 10
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn impassable_cells_and_descents() -> anyhow::Result<()> {
+        let map = TopographicMap::parse(
+            ".....0.
+..4321.
+..5..2.
+..6543.
+..7..4.
+..8765.
+..9....",
+        )?;
+        assert_eq!(map.survey(&TrailRules::new())?.rating, 3);
+
+        let inverted: String = INPUT
+            .chars()
+            .map(|c| c.to_digit(10).map_or(c, |h| char::from(b'9' - h as u8)))
+            .collect();
+        let descents = TrailRules::new().with_heights(9, 0).with_steps(-1..=-1);
+        assert_eq!(
+            TopographicMap::parse(&inverted)?.survey(&descents)?,
+            Survey {
+                score: 36,
+                rating: 81
+            }
+        );
+        assert!(map.survey(&TrailRules::new().with_steps(-1..=1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn trails_lists_positions_up_to_limit() -> anyhow::Result<()> {
+        let map = TopographicMap::parse("012345\n123456\n234567\n345678\n456789")?;
+        let trails = map.trails(&TrailRules::new(), (0, 0), 5)?;
+        assert_eq!(trails.len(), 5);
+        assert!(trails
+            .iter()
+            .all(|trail| trail.len() == 10 && trail[9] == (4, 5)));
+        assert_eq!(map.trails(&TrailRules::new(), (0, 0), 1000)?.len(), 126);
+
+        let steep = TrailRules::new().with_heights(0, 8).with_steps(1..=2);
+        let map = TopographicMap::parse("02468\n.....\n0.1.2")?;
+        assert_eq!(
+            map.trails(&steep, (0, 0), 10)?,
+            vec![vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)]]
+        );
+
+        let diagonal = TrailRules::new().with_heights(0, 2);
+        let map = TopographicMap::parse("0..\n.1.\n..2")?;
+        assert!(map.trails(&diagonal, (0, 0), 10)?.is_empty());
+        assert_eq!(
+            map.trails(&diagonal.with_diagonal_moves(), (0, 0), 10)?,
+            vec![vec![(0, 0), (1, 1), (2, 2)]]
+        );
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
 
 use anyhow::anyhow;
 use nom::{
@@ -9,54 +9,331 @@ use nom::{
 };
 
 pub fn part1(input: &str) -> anyhow::Result<u64> {
-    let (_, mut stones) = parse(input).map_err(|e| anyhow!("Failed to parse input: {e}"))?;
-    expand(&mut stones, 25);
-    Ok(stones.values().sum())
+    let stones = Stones::parse(input)?;
+    Ok(StoneRules::standard().blink(&stones, 25)?.count())
 }
 
 pub fn part2(input: &str) -> anyhow::Result<u64> {
-    let (_, mut stones) = parse(input).map_err(|e| anyhow!("Failed to parse input: {e}"))?;
-    expand(&mut stones, 75);
-    Ok(stones.values().sum())
+    let stones = Stones::parse(input)?;
+    Ok(StoneRules::standard().blink(&stones, 75)?.count())
 }
 
-fn expand(stones: &mut HashMap<u64, u64>, iterations: u32) {
-    for _ in 0..iterations {
-        let mut next_expansion = HashMap::default();
-        stones.iter().for_each(|(stone, count)| {
-            rule1(stone)
-                .or_else(|| rule2(stone))
-                .unwrap_or_else(|| rule3(stone))
-                .iter()
-                .for_each(|new_stone| *next_expansion.entry(*new_stone).or_default() += count)
-        });
+/// A rule that may replace a stone with new stones when blinking.
+pub trait StoneRule: Send + Sync {
+    /// The stones that replace `stone`, or `None` if the rule does not apply to it. Fails if a
+    /// new stone's value does not fit in a `u64`.
+    fn apply(&self, stone: u64) -> anyhow::Result<Option<Vec<u64>>>;
+}
+
+/// Replaces stones engraved with `from` by a stone engraved with `to`.
+pub struct Replace {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl StoneRule for Replace {
+    fn apply(&self, stone: u64) -> anyhow::Result<Option<Vec<u64>>> {
+        Ok((stone == self.from).then(|| vec![self.to]))
+    }
+}
 
-        *stones = next_expansion;
+/// Splits stones with an even number of digits in `base` into their left and right halves.
+pub struct SplitEvenDigits {
+    pub base: u64,
+}
+
+impl SplitEvenDigits {
+    pub const DECIMAL: SplitEvenDigits = SplitEvenDigits { base: 10 };
+}
+
+impl StoneRule for SplitEvenDigits {
+    fn apply(&self, stone: u64) -> anyhow::Result<Option<Vec<u64>>> {
+        let num_digits = stone.checked_ilog(self.base).unwrap_or(0) + 1;
+        if num_digits.is_multiple_of(2) {
+            let scaling_factor = self.base.pow(num_digits / 2);
+            Ok(Some(vec![stone / scaling_factor, stone % scaling_factor]))
+        } else {
+            Ok(None)
+        }
     }
 }
 
-fn rule1(stone: &u64) -> Option<Vec<u64>> {
-    if *stone == 0 {
-        Some(vec![1])
-    } else {
-        None
+/// Multiplies every stone by a factor.
+pub struct Multiply(pub u64);
+
+impl StoneRule for Multiply {
+    fn apply(&self, stone: u64) -> anyhow::Result<Option<Vec<u64>>> {
+        let product = stone
+            .checked_mul(self.0)
+            .ok_or(anyhow!("Multiplying {stone} by {} overflows", self.0))?;
+        Ok(Some(vec![product]))
     }
 }
 
-fn rule2(stone: &u64) -> Option<Vec<u64>> {
-    let num_digits = stone.ilog10() + 1;
-    if num_digits % 2 == 0 {
-        let scaling_factor = 10u64.pow(num_digits / 2);
-        let left = stone / scaling_factor;
-        let right = stone - (left * scaling_factor);
-        Some(vec![left, right])
-    } else {
-        None
+/// An ordered list of rules. Each stone is replaced by the first rule that applies to it, and
+/// stones no rule applies to are left as they are.
+#[derive(Default)]
+pub struct StoneRules {
+    rules: Vec<Box<dyn StoneRule>>,
+}
+
+impl StoneRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The rules from the puzzle: 0 becomes 1, even-digit stones split, others are multiplied
+    /// by 2024.
+    pub fn standard() -> Self {
+        Self::new()
+            .with(Replace { from: 0, to: 1 })
+            .with(SplitEvenDigits::DECIMAL)
+            .with(Multiply(2024))
+    }
+
+    pub fn with(mut self, rule: impl StoneRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// The stones left after blinking. Fails if a stone's value or the number of stones
+    /// overflows a `u64`.
+    pub fn blink(&self, stones: &Stones, blinks: u32) -> anyhow::Result<Stones> {
+        let mut transitions = HashMap::<u64, Vec<u64>>::default();
+        let mut counts = stones.counts.clone();
+        let mut next_counts = HashMap::<u64, u64>::default();
+        for _ in 0..blinks {
+            for (stone, count) in counts.drain() {
+                let new_stones = match transitions.entry(stone) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(self.successors(stone)?),
+                };
+                for new_stone in new_stones.iter() {
+                    let new_count = next_counts.entry(*new_stone).or_default();
+                    *new_count = new_count
+                        .checked_add(count)
+                        .ok_or(anyhow!("Number of stones overflows"))?;
+                }
+            }
+
+            // Every count is at most the total, so checking the total checks them all.
+            next_counts
+                .values()
+                .try_fold(0u64, |total, count| total.checked_add(*count))
+                .ok_or(anyhow!("Number of stones overflows"))?;
+            std::mem::swap(&mut counts, &mut next_counts);
+        }
+
+        Ok(Stones { counts })
+    }
+
+    /// Finds every value reachable by blinking `stones`, failing if there are more than
+    /// `max_values` of them.
+    pub fn transitions(&self, stones: &Stones, max_values: usize) -> anyhow::Result<Transitions> {
+        let mut values = stones.counts.keys().copied().collect::<Vec<_>>();
+        values.sort_unstable();
+        let mut index = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (*value, index))
+            .collect::<HashMap<_, _>>();
+        let mut successors = vec![];
+
+        while let Some(value) = values.get(successors.len()).copied() {
+            let mut next = vec![];
+            for new_stone in self.successors(value)? {
+                let next_index = *index.entry(new_stone).or_insert_with(|| {
+                    values.push(new_stone);
+                    values.len() - 1
+                });
+                next.push(next_index);
+            }
+            successors.push(next);
+
+            if values.len() > max_values {
+                return Err(anyhow!("More than {max_values} stone values are reachable"));
+            }
+        }
+
+        Ok(Transitions {
+            values,
+            index,
+            successors,
+        })
+    }
+
+    fn successors(&self, stone: u64) -> anyhow::Result<Vec<u64>> {
+        for rule in &self.rules {
+            if let Some(new_stones) = rule.apply(stone)? {
+                return Ok(new_stones);
+            }
+        }
+
+        Ok(vec![stone])
     }
 }
 
-fn rule3(stone: &u64) -> Vec<u64> {
-    vec![stone * 2024]
+/// The finite set of stone values reachable under some rules, and what each value turns into.
+#[derive(Clone, Debug)]
+pub struct Transitions {
+    values: Vec<u64>,
+    index: HashMap<u64, usize>,
+    successors: Vec<Vec<usize>>,
+}
+
+impl Transitions {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The number of stones after blinking `stones`, modulo `modulus`.
+    ///
+    /// Blinks by repeatedly squaring the transition matrix, or one blink at a time when that is
+    /// cheaper, as it is for few blinks or many reachable values.
+    pub fn count_modulo(&self, stones: &Stones, blinks: u64, modulus: u64) -> anyhow::Result<u64> {
+        if modulus == 0 {
+            return Err(anyhow!("Modulus must be positive"));
+        }
+
+        let mut initial = vec![0; self.len()];
+        for (stone, count) in &stones.counts {
+            let index = self
+                .index
+                .get(stone)
+                .ok_or(anyhow!("Stone {stone} is not among the transitions"))?;
+            initial[*index] = count % modulus;
+        }
+
+        let num_transitions = self.successors.iter().map(Vec::len).sum::<usize>() as u64;
+        let iteration_cost = blinks.saturating_mul(num_transitions);
+        let squaring_cost = (self.len() as u64)
+            .saturating_pow(3)
+            .saturating_mul(u64::from(blinks.checked_ilog2().unwrap_or(0)) + 1);
+        let count = if iteration_cost <= squaring_cost {
+            self.iterate(initial, blinks, modulus)
+        } else {
+            self.square(initial, blinks, modulus)
+        };
+
+        Ok(count)
+    }
+
+    fn iterate(&self, mut counts: Vec<u64>, blinks: u64, modulus: u64) -> u64 {
+        let mut next_counts = vec![0; self.len()];
+        for _ in 0..blinks {
+            next_counts.fill(0);
+            for (index, count) in counts.iter().enumerate() {
+                for next in &self.successors[index] {
+                    next_counts[*next] = add_mod(next_counts[*next], *count, modulus);
+                }
+            }
+            std::mem::swap(&mut counts, &mut next_counts);
+        }
+
+        counts
+            .into_iter()
+            .fold(0, |total, count| add_mod(total, count, modulus))
+    }
+
+    /// Computes `initial · M^blinks · 1`, where `M[i][j]` counts the stones of value `j` that a
+    /// stone of value `i` turns into.
+    fn square(&self, initial: Vec<u64>, mut blinks: u64, modulus: u64) -> u64 {
+        let n = self.len();
+        let mut matrix = vec![vec![0; n]; n];
+        for (index, successors) in self.successors.iter().enumerate() {
+            for next in successors {
+                matrix[index][*next] = add_mod(matrix[index][*next], 1, modulus);
+            }
+        }
+
+        // Stones each value turns into after the blinks consumed so far.
+        let mut totals = vec![1 % modulus; n];
+        while blinks > 0 {
+            if blinks & 1 == 1 {
+                totals = multiply_vector(&matrix, &totals, modulus);
+            }
+            blinks >>= 1;
+            if blinks > 0 {
+                matrix = multiply_matrices(&matrix, &matrix, modulus);
+            }
+        }
+
+        initial
+            .iter()
+            .zip(&totals)
+            .fold(0, |total, (count, stones)| {
+                add_mod(total, mul_mod(*count, *stones, modulus), modulus)
+            })
+    }
+}
+
+fn add_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    ((u128::from(a) + u128::from(b)) % u128::from(modulus)) as u64
+}
+
+fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    (u128::from(a) * u128::from(b) % u128::from(modulus)) as u64
+}
+
+fn multiply_vector(matrix: &[Vec<u64>], vector: &[u64], modulus: u64) -> Vec<u64> {
+    matrix
+        .iter()
+        .map(|row| {
+            row.iter().zip(vector).fold(0, |total, (a, b)| {
+                add_mod(total, mul_mod(*a, *b, modulus), modulus)
+            })
+        })
+        .collect()
+}
+
+fn multiply_matrices(left: &[Vec<u64>], right: &[Vec<u64>], modulus: u64) -> Vec<Vec<u64>> {
+    left.iter()
+        .map(|row| {
+            let mut product = vec![0; right.len()];
+            for (a, right_row) in row.iter().zip(right) {
+                if *a == 0 {
+                    continue;
+                }
+                for (entry, b) in product.iter_mut().zip(right_row) {
+                    *entry = add_mod(*entry, mul_mod(*a, *b, modulus), modulus);
+                }
+            }
+            product
+        })
+        .collect()
+}
+
+/// A multiset of stones, stored as the number of stones engraved with each value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stones {
+    counts: HashMap<u64, u64>,
+}
+
+impl Stones {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let (_, counts) = parse(input.trim()).map_err(|e| anyhow!("Failed to parse input: {e}"))?;
+        Ok(Stones { counts })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// The number of stones engraved with each value, in order of value.
+    pub fn distribution(&self) -> BTreeMap<u64, u64> {
+        self.counts
+            .iter()
+            .map(|(stone, count)| (*stone, *count))
+            .collect()
+    }
 }
 
 fn parse(input: &str) -> IResult<&str, HashMap<u64, u64>> {
@@ -73,7 +350,9 @@ fn parse(input: &str) -> IResult<&str, HashMap<u64, u64>> {
 
 #[cfg(test)]
 mod tests {
-    use super::part1;
+    use super::{part1, Multiply, Replace, SplitEvenDigits, StoneRule, StoneRules, Stones};
+
+    const MODULUS: u64 = 1_000_000_007;
 
     const INPUT: &str = "125 17";
 
@@ -82,4 +361,79 @@ mod tests {
         assert_eq!(part1(INPUT)?, 55312);
         Ok(())
     }
+
+    #[test]
+    fn blink_reports_count_distribution_and_distinct_stones() -> anyhow::Result<()> {
+        let stones = Stones::parse(INPUT)?;
+        let blinked = StoneRules::standard().blink(&stones, 6)?;
+
+        // 2097446912 14168 4048 2 0 2 4 40 48 2024 40 48 80 96 2 8 6 7 6 0 3 2
+        assert_eq!(blinked.count(), 22);
+        assert_eq!(blinked.distinct(), 15);
+        assert_eq!(blinked.distribution()[&2], 4);
+        assert_eq!(blinked.distribution()[&48], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn rules_can_be_configured() -> anyhow::Result<()> {
+        struct Halve;
+
+        impl StoneRule for Halve {
+            fn apply(&self, stone: u64) -> anyhow::Result<Option<Vec<u64>>> {
+                Ok(stone.is_multiple_of(2).then(|| vec![stone / 2, stone / 2]))
+            }
+        }
+
+        // 5 (0b101, three digits) -> 15 (0b1111) -> 3 (0b11), 3 -> 1, 1, 1, 1
+        let binary = StoneRules::new()
+            .with(SplitEvenDigits { base: 2 })
+            .with(Multiply(3));
+        let blinked = binary.blink(&Stones::parse("5")?, 3)?;
+        assert_eq!(blinked.distribution(), [(1, 4)].into_iter().collect());
+
+        let halving = StoneRules::new()
+            .with(Replace { from: 0, to: 0 })
+            .with(Halve);
+        assert_eq!(halving.blink(&Stones::parse("8 3 0")?, 3)?.count(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn blink_detects_overflow() -> anyhow::Result<()> {
+        let stones = Stones::parse(INPUT)?;
+        assert!(StoneRules::standard().blink(&stones, 200).is_err());
+
+        let doubling = StoneRules::new().with(Multiply(2));
+        assert!(doubling.blink(&Stones::parse("1")?, 63).is_ok());
+        assert!(doubling.blink(&Stones::parse("1")?, 64).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn transitions_count_stones_modulo() -> anyhow::Result<()> {
+        let rules = StoneRules::standard();
+        let stones = Stones::parse(INPUT)?;
+        let transitions = rules.transitions(&stones, 10_000)?;
+
+        assert_eq!(
+            transitions.count_modulo(&stones, 75, u64::MAX)?,
+            rules.blink(&stones, 75)?.count()
+        );
+        assert!(rules.transitions(&stones, transitions.len() - 1).is_err());
+
+        let zero = Stones::parse("0")?;
+        let transitions = rules.transitions(&zero, 10_000)?;
+        assert_eq!(transitions.len(), 54);
+        for blinks in [0, 1, 75, 1000, 12_345] {
+            let mut counts = vec![0; transitions.len()];
+            counts[0] = 1;
+            assert_eq!(
+                transitions.square(counts.clone(), blinks, MODULUS),
+                transitions.iterate(counts, blinks, MODULUS),
+            );
+        }
+        assert!(transitions.count_modulo(&zero, 10_000_000_000, MODULUS)? < MODULUS);
+        Ok(())
+    }
 }
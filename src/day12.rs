@@ -1,199 +1,389 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
+use itertools::Itertools;
 use ndarray::Array2;
 
 pub fn part1(input: &str) -> usize {
-    let map = parse(input);
-    let regions = segment(&map);
-    regions
+    Garden::parse(input)
+        .regions()
         .iter()
-        .map(|region| area(region) * perimeter(&map, region))
+        .map(|region| region.area() * region.perimeter())
         .sum()
 }
 
 pub fn part2(input: &str) -> usize {
-    let map = parse(input);
-    let regions = segment(&map);
-    regions
+    Garden::parse(input)
+        .regions()
         .iter()
-        .map(|region| area(region) * edges(&map, region))
+        .map(|region| region.area() * region.sides())
         .sum()
 }
 
-type Coordinate = (usize, usize);
-type Region = HashSet<Coordinate>;
+/// A cell of the map, as `(row, col)`.
+pub type Coordinate = (usize, usize);
 
-fn area(region: &Region) -> usize {
-    region.len()
+/// A corner between cells, as `(row, col)`. Cell `(row, col)` spans vertices `(row, col)` to
+/// `(row + 1, col + 1)`.
+pub type Vertex = (usize, usize);
+
+/// A map of plants, segmented into regions.
+#[derive(Debug)]
+pub struct Garden {
+    /// The region each cell belongs to, numbered in reading order of their first cells.
+    labels: Array2<u32>,
+    regions: Vec<Measurements>,
 }
 
-fn perimeter(map: &Array2<char>, region: &Region) -> usize {
-    region
-        .iter()
-        .map(|coordinate| {
-            [
-                top(map, coordinate),
-                right(map, coordinate),
-                bottom(map, coordinate),
-                left(map, coordinate),
-            ]
-            .iter()
-            .filter(|neighbor| {
-                neighbor
-                    .map(|neighbor| map[neighbor] != map[*coordinate])
-                    .unwrap_or(true)
+#[derive(Clone, Copy, Debug)]
+struct Measurements {
+    plant: char,
+    area: usize,
+    perimeter: usize,
+    corners: usize,
+    bounding_box: BoundingBox,
+}
+
+impl Garden {
+    pub fn parse(input: &str) -> Self {
+        let map = parse(input);
+        let labels = label(&map);
+        let regions = measure(&map, &labels);
+        Garden { labels, regions }
+    }
+
+    /// Every region, ordered by its first cell in reading order.
+    pub fn regions(&self) -> Vec<Region<'_>> {
+        (0..self.regions.len() as u32)
+            .map(|label| Region {
+                garden: self,
+                label,
             })
-            .count()
-        })
-        .sum()
+            .collect()
+    }
 }
 
-fn edges(map: &Array2<char>, region: &Region) -> usize {
-    let mut num_edges = 0;
+/// A connected area of a single type of plant.
+#[derive(Clone, Copy, Debug)]
+pub struct Region<'a> {
+    garden: &'a Garden,
+    label: u32,
+}
 
-    for coordinate in region {
-        let tl = in_region(region, top_left(map, coordinate));
-        let t = in_region(region, top(map, coordinate));
-        let tr = in_region(region, top_right(map, coordinate));
-        let l = in_region(region, left(map, coordinate));
-        let r = in_region(region, right(map, coordinate));
-        let bl = in_region(region, bottom_left(map, coordinate));
-        let b = in_region(region, bottom(map, coordinate));
-        let br = in_region(region, bottom_right(map, coordinate));
+/// The smallest rectangle of cells containing a region, with both corners inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub top_left: Coordinate,
+    pub bottom_right: Coordinate,
+}
 
-        if (!t && !r) || (t && r && !tr) {
-            num_edges += 1;
-        }
+/// A closed outline through the corners of cells, listing only the vertices where it turns.
+///
+/// Outer boundaries run clockwise on the map and holes run counterclockwise, so the region is
+/// always to the right. Outlines may touch themselves where cells meet diagonally.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Polygon {
+    pub vertices: Vec<Vertex>,
+}
 
-        if (!r && !b) || (r && b && !br) {
-            num_edges += 1
-        }
+/// Cells of other plants completely enclosed by a region.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hole {
+    /// The enclosed cells, in reading order.
+    pub cells: Vec<Coordinate>,
+    pub outline: Polygon,
+}
 
-        if (!b && !l) || (b && l && !bl) {
-            num_edges += 1
-        }
+impl Region<'_> {
+    pub fn plant(&self) -> char {
+        self.measurements().plant
+    }
 
-        if (!l && !t) || (l && t && !tl) {
-            num_edges += 1
-        }
+    pub fn area(&self) -> usize {
+        self.measurements().area
     }
 
-    num_edges
-}
+    pub fn perimeter(&self) -> usize {
+        self.measurements().perimeter
+    }
 
-fn in_region(region: &HashSet<(usize, usize)>, coordinate: Option<Coordinate>) -> bool {
-    coordinate
-        .map(|neighbor| region.contains(&neighbor))
-        .unwrap_or(false)
-}
+    /// The number of straight fence sides, which is the number of corners on all outlines.
+    pub fn sides(&self) -> usize {
+        self.measurements().corners
+    }
 
-fn segment(map: &Array2<char>) -> Vec<Region> {
-    let mut visited = HashSet::<Coordinate>::new();
-    let mut regions = Vec::<Region>::new();
+    pub fn bounding_box(&self) -> BoundingBox {
+        self.measurements().bounding_box
+    }
 
-    for (coordinate, plant_type) in map.indexed_iter() {
-        if !visited.insert(coordinate) {
-            continue;
-        }
+    fn measurements(&self) -> &Measurements {
+        &self.garden.regions[self.label as usize]
+    }
 
-        let region = fill(map, coordinate, plant_type);
-        visited.extend(region.clone());
-        regions.push(region);
+    /// The outer boundary of the region.
+    pub fn outline(&self) -> Polygon {
+        self.loops()
+            .into_iter()
+            .find(|polygon| signed_area(polygon) > 0)
+            .unwrap_or(Polygon { vertices: vec![] })
     }
 
-    regions
-}
+    /// The pockets of other plants inside the region, ordered by their first cell.
+    ///
+    /// Cells of the region that touch diagonally are treated as connected, so pockets that only
+    /// touch each other at a corner are separate holes.
+    pub fn holes(&self) -> Vec<Hole> {
+        self.loops()
+            .into_iter()
+            .filter(|polygon| signed_area(polygon) < 0)
+            .map(|outline| {
+                // A hole's first vertex is the top left corner of its first cell.
+                let mut cells = vec![];
+                let mut visited = HashSet::from([outline.vertices[0]]);
+                let mut stack = vec![outline.vertices[0]];
+                while let Some(cell) = stack.pop() {
+                    cells.push(cell);
+                    for direction in Direction::ALL {
+                        if self.contains_step(cell, direction) {
+                            continue;
+                        }
+                        if let Some(next) = direction.step(cell) {
+                            if visited.insert(next) {
+                                stack.push(next);
+                            }
+                        }
+                    }
+                }
+                cells.sort_unstable();
+
+                Hole { cells, outline }
+            })
+            .collect()
+    }
 
-fn fill(map: &Array2<char>, coordinate: Coordinate, plant_type: &char) -> Region {
-    let mut region = Region::default();
-    let mut stack = vec![coordinate];
+    fn contains_step(&self, coordinate: Coordinate, direction: Direction) -> bool {
+        direction
+            .step(coordinate)
+            .is_some_and(|neighbor| self.garden.labels.get(neighbor) == Some(&self.label))
+    }
 
-    while let Some(new_coordinate) = stack.pop() {
-        if map[new_coordinate] != *plant_type {
-            continue;
+    /// Every closed boundary of the region, starting from its top left vertex.
+    fn loops(&self) -> Vec<Polygon> {
+        let BoundingBox {
+            top_left,
+            bottom_right,
+        } = self.bounding_box();
+        let cells = (top_left.0..=bottom_right.0)
+            .cartesian_product(top_left.1..=bottom_right.1)
+            .filter(|cell| self.garden.labels[*cell] == self.label);
+
+        let mut outgoing = HashMap::<Vertex, Vec<Direction>>::new();
+        for (row, col) in cells {
+            let edges = [
+                (Direction::Up, (row, col), Direction::Right),
+                (Direction::Right, (row, col + 1), Direction::Down),
+                (Direction::Down, (row + 1, col + 1), Direction::Left),
+                (Direction::Left, (row + 1, col), Direction::Up),
+            ];
+            for (side, start, direction) in edges {
+                if !self.contains_step((row, col), side) {
+                    outgoing.entry(start).or_default().push(direction);
+                }
+            }
         }
 
-        if !region.insert(new_coordinate) {
-            continue;
+        let mut starts = outgoing.keys().copied().collect::<Vec<_>>();
+        starts.sort_unstable();
+        let mut loops = vec![];
+        for start in starts {
+            // The top left vertex of an untraced loop is never a pinch point, so it has a single
+            // outgoing edge.
+            let Some(mut direction) = outgoing.get_mut(&start).and_then(Vec::pop) else {
+                continue;
+            };
+            let mut vertices = vec![start];
+            let mut vertex = direction.follow(start);
+            while vertex != start {
+                let directions = outgoing.get_mut(&vertex).expect("boundary is closed");
+                // Where two cells of the region touch diagonally, cross over to the other cell
+                // rather than wrapping around this one.
+                let index = directions
+                    .iter()
+                    .position(|next| directions.len() == 1 || *next == direction.turn_left())
+                    .expect("boundary continues");
+                let next = directions.swap_remove(index);
+                if next != direction {
+                    vertices.push(vertex);
+                }
+                direction = next;
+                vertex = direction.follow(vertex);
+            }
+            loops.push(Polygon { vertices });
         }
 
-        stack.extend(orthogonal_neighbors(map, &new_coordinate));
+        loops
     }
+}
 
-    region
+/// Twice the area enclosed by a polygon, positive when it runs clockwise on the map.
+fn signed_area(polygon: &Polygon) -> isize {
+    let vertices = &polygon.vertices;
+    (0..vertices.len())
+        .map(|index| {
+            let (row, col) = vertices[index];
+            let (next_row, next_col) = vertices[(index + 1) % vertices.len()];
+            (col * next_row) as isize - (next_col * row) as isize
+        })
+        .sum()
 }
 
-fn orthogonal_neighbors(map: &Array2<char>, coordinate: &Coordinate) -> Vec<Coordinate> {
-    top(map, coordinate)
-        .into_iter()
-        .chain(right(map, coordinate))
-        .chain(bottom(map, coordinate))
-        .chain(left(map, coordinate))
-        .collect()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Right,
+    Down,
+    Left,
 }
 
-fn top_left(_map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row > 0 && *col > 0 {
-        Some((row - 1, col - 1))
-    } else {
-        None
+impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Right,
+        Direction::Down,
+        Direction::Left,
+    ];
+
+    fn turn_left(&self) -> Direction {
+        match self {
+            Direction::Up => Direction::Left,
+            Direction::Right => Direction::Up,
+            Direction::Down => Direction::Right,
+            Direction::Left => Direction::Down,
+        }
     }
-}
 
-fn top(_map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row > 0 {
-        Some((row - 1, *col))
-    } else {
-        None
+    /// The neighboring cell in this direction, if it is not off the top or left of the map.
+    fn step(&self, (row, col): Coordinate) -> Option<Coordinate> {
+        match self {
+            Direction::Up => Some((row.checked_sub(1)?, col)),
+            Direction::Right => Some((row, col + 1)),
+            Direction::Down => Some((row + 1, col)),
+            Direction::Left => Some((row, col.checked_sub(1)?)),
+        }
     }
-}
 
-fn top_right(map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row > 0 && *col < map.dim().1 - 1 {
-        Some((row - 1, col + 1))
-    } else {
-        None
+    /// The next vertex along an edge. Boundary edges never leave the top or left of the map.
+    fn follow(&self, vertex: Vertex) -> Vertex {
+        self.step(vertex).expect("edge stays on the map")
     }
 }
 
-fn left(_map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *col > 0 {
-        Some((*row, col - 1))
-    } else {
-        None
+/// Labels connected cells of the same plant with a raster scan, merging labels that meet with a
+/// union-find, then renumbers the labels in order of their first cell.
+fn label(map: &Array2<char>) -> Array2<u32> {
+    let mut labels = Array2::<u32>::zeros(map.dim());
+    let mut parents = Vec::<u32>::new();
+
+    for ((row, col), plant) in map.indexed_iter() {
+        let up = (row > 0 && map[(row - 1, col)] == *plant).then(|| labels[(row - 1, col)]);
+        let left = (col > 0 && map[(row, col - 1)] == *plant).then(|| labels[(row, col - 1)]);
+        labels[(row, col)] = match (up, left) {
+            (Some(up), Some(left)) => union(&mut parents, up, left),
+            (Some(label), None) | (None, Some(label)) => label,
+            (None, None) => {
+                parents.push(parents.len() as u32);
+                parents.len() as u32 - 1
+            }
+        };
     }
-}
 
-fn right(map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *col < map.dim().1 - 1 {
-        Some((*row, col + 1))
-    } else {
-        None
+    let mut renumbered = vec![u32::MAX; parents.len()];
+    let mut num_regions = 0;
+    for label in labels.iter_mut() {
+        let root = find(&mut parents, *label) as usize;
+        if renumbered[root] == u32::MAX {
+            renumbered[root] = num_regions;
+            num_regions += 1;
+        }
+        *label = renumbered[root];
     }
+
+    labels
 }
 
-fn bottom_left(map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row < map.dim().0 - 1 && *col > 0 {
-        Some((row + 1, col - 1))
-    } else {
-        None
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    let mut root = label;
+    while parents[root as usize] != root {
+        root = parents[root as usize];
+    }
+    while parents[label as usize] != root {
+        let parent = parents[label as usize];
+        parents[label as usize] = root;
+        label = parent;
     }
+
+    root
 }
 
-fn bottom(map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row < map.dim().0 - 1 {
-        Some((row + 1, *col))
-    } else {
-        None
-    }
+fn union(parents: &mut [u32], a: u32, b: u32) -> u32 {
+    let (a, b) = (find(parents, a), find(parents, b));
+    let (root, child) = if a <= b { (a, b) } else { (b, a) };
+    parents[child as usize] = root;
+    root
 }
 
-fn bottom_right(map: &Array2<char>, (row, col): &Coordinate) -> Option<Coordinate> {
-    if *row < map.dim().0 - 1 && *col < map.dim().1 - 1 {
-        Some((row + 1, col + 1))
-    } else {
-        None
+/// Measures every region in a single raster scan.
+///
+/// Each cell adds a fence for each neighbor in another region, and a corner for each of its
+/// four corners that is either convex (both adjacent neighbors outside the region) or concave
+/// (both inside but the diagonal neighbor outside).
+fn measure(map: &Array2<char>, labels: &Array2<u32>) -> Vec<Measurements> {
+    let mut regions = Vec::<Measurements>::new();
+    let (num_rows, num_cols) = labels.dim();
+
+    for ((row, col), label) in labels.indexed_iter() {
+        let same = |row_offset: isize, col_offset: isize| {
+            row.checked_add_signed(row_offset)
+                .zip(col.checked_add_signed(col_offset))
+                .filter(|(row, col)| *row < num_rows && *col < num_cols)
+                .is_some_and(|neighbor| labels[neighbor] == *label)
+        };
+        let (up, right, down, left) = (same(-1, 0), same(0, 1), same(1, 0), same(0, -1));
+        let corners = [
+            (up, right, same(-1, 1)),
+            (right, down, same(1, 1)),
+            (down, left, same(1, -1)),
+            (left, up, same(-1, -1)),
+        ]
+        .iter()
+        .filter(|(first, second, diagonal)| (!first && !second) || (*first && *second && !diagonal))
+        .count();
+        let fences = [up, right, down, left]
+            .iter()
+            .filter(|same| !**same)
+            .count();
+
+        if *label as usize == regions.len() {
+            regions.push(Measurements {
+                plant: map[(row, col)],
+                area: 0,
+                perimeter: 0,
+                corners: 0,
+                bounding_box: BoundingBox {
+                    top_left: (row, col),
+                    bottom_right: (row, col),
+                },
+            });
+        }
+        let region = &mut regions[*label as usize];
+        region.area += 1;
+        region.perimeter += fences;
+        region.corners += corners;
+        let bounding_box = &mut region.bounding_box;
+        bounding_box.top_left.1 = bounding_box.top_left.1.min(col);
+        bounding_box.bottom_right = (row, bounding_box.bottom_right.1.max(col));
     }
+
+    regions
 }
 
 fn parse(input: &str) -> Array2<char> {
@@ -209,7 +399,7 @@ fn parse(input: &str) -> Array2<char> {
 
 #[cfg(test)]
 mod tests {
-    use super::{part1, part2};
+    use super::{part1, part2, BoundingBox, Garden, Hole, Polygon};
 
     const INPUT: &str = "RRRRIICCFF
 RRRRIICCCF
@@ -231,4 +421,79 @@ MMMISSJEEE";
     fn part2_returns_discount_price_of_fencing() {
         assert_eq!(part2(INPUT), 1206);
     }
+
+    #[test]
+    fn sides_count_holes_touching_at_a_corner() {
+        let input = "AAAAAA
+AAABBA
+AAABBA
+ABBAAA
+ABBAAA
+AAAAAA";
+        assert_eq!(part2(input), 368);
+
+        let garden = Garden::parse(input);
+        let regions = garden.regions();
+        assert_eq!(regions[0].holes().len(), 2);
+        assert_eq!(regions[0].sides(), 12);
+    }
+
+    #[test]
+    fn regions_expose_outlines_and_holes() {
+        let garden = Garden::parse("AAAA\nABBA\nAAAA");
+        let regions = garden.regions();
+        let (outer, inner) = (&regions[0], &regions[1]);
+
+        assert_eq!(
+            (outer.plant(), outer.area(), outer.perimeter()),
+            ('A', 10, 20)
+        );
+        assert_eq!(outer.sides(), 8);
+        assert_eq!(
+            outer.bounding_box(),
+            BoundingBox {
+                top_left: (0, 0),
+                bottom_right: (2, 3)
+            }
+        );
+        assert_eq!(
+            outer.outline(),
+            Polygon {
+                vertices: vec![(0, 0), (0, 4), (3, 4), (3, 0)]
+            }
+        );
+        assert_eq!(
+            outer.holes(),
+            vec![Hole {
+                cells: vec![(1, 1), (1, 2)],
+                outline: Polygon {
+                    vertices: vec![(1, 1), (2, 1), (2, 3), (1, 3)]
+                }
+            }]
+        );
+
+        assert_eq!(
+            inner.outline(),
+            Polygon {
+                vertices: vec![(1, 1), (1, 3), (2, 3), (2, 1)]
+            }
+        );
+        assert!(inner.holes().is_empty());
+    }
+
+    #[test]
+    fn labels_merge_regions_joined_further_down() {
+        // The two arms of the U are separate labels until the bottom row joins them.
+        let garden = Garden::parse("ABA\nABA\nAAA");
+        let regions = garden.regions();
+        assert_eq!(regions.len(), 2);
+        assert_eq!((regions[0].area(), regions[0].sides()), (7, 8));
+
+        let stripes = (0..300)
+            .map(|_| "AB".repeat(150))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(part1(&stripes), 300 * 300 * 602);
+        assert_eq!(part2(&stripes), 300 * 300 * 4);
+    }
 }
@@ -1,132 +1,529 @@
 use anyhow::anyhow;
+use itertools::Itertools;
 use nom::{
     bytes::complete::tag,
-    character::{self, complete::line_ending},
-    combinator::map,
-    multi::separated_list1,
-    sequence::{preceded, separated_pair, terminated, tuple},
+    character::{
+        self,
+        complete::{anychar, line_ending, multispace0, one_of},
+    },
+    combinator::{all_consuming, map, opt, verify},
+    multi::{many1, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
 pub fn part1(input: &str) -> anyhow::Result<i64> {
-    let (_, claw_machines) = parse(input).map_err(|e| anyhow!("Unable to parse input: {e}"))?;
-    Ok(claw_machines.iter().filter_map(optimal_cost).sum())
+    fewest_tokens(input, &Solver::new().with_press_limit(100))
 }
 
 pub fn part2(input: &str) -> anyhow::Result<i64> {
-    let (_, claw_machines) = parse(input).map_err(|e| anyhow!("Unable to parse input: {e}"))?;
-    Ok(claw_machines
-        .iter()
-        .filter_map(corrected_optimal_cost)
-        .sum())
+    fewest_tokens(input, &Solver::new().with_prize_offset(10_000_000_000_000))
 }
 
-fn optimal_cost(cm: &ClawMachine) -> Option<i64> {
-    let b = (cm.prize.x * cm.a.dy - cm.prize.y * cm.a.dx) / (cm.a.dy * cm.b.dx - cm.a.dx * cm.b.dy);
-    let a = (cm.prize.x - cm.b.dx * b) / cm.a.dx;
+/// The total cost of winning every prize that can be won.
+pub fn fewest_tokens(input: &str, solver: &Solver) -> anyhow::Result<i64> {
+    let mut total = 0i128;
+    for claw_machine in parse(input)? {
+        if let Some(presses) = solver.cheapest(&claw_machine)? {
+            total = total
+                .checked_add(presses.cost)
+                .ok_or(anyhow!("Total cost overflows"))?;
+        }
+    }
+
+    i64::try_from(total).map_err(|e| anyhow!("Total cost {total} is too large: {e}"))
+}
+
+/// Finds the cheapest way to win a prize with exact integer arithmetic, searching outwards from
+/// the cheapest fractional solution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Solver {
+    prize_offset: i64,
+    press_limit: Option<i64>,
+    costs: Vec<u32>,
+}
+
+/// How many times to press each button, and what that costs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Presses {
+    pub counts: Vec<i128>,
+    pub cost: i128,
+}
 
-    if (0..=100).contains(&a)
-        && (0..=100).contains(&b)
-        && a * cm.a.dx + b * cm.b.dx == cm.prize.x
-        && a * cm.a.dy + b * cm.b.dy == cm.prize.y
-    {
-        Some(3 * a + b)
-    } else {
-        None
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn corrected_optimal_cost(cm: &ClawMachine) -> Option<i64> {
-    let prize_x = cm.prize.x + 10_000_000_000_000;
-    let prize_y = cm.prize.y + 10_000_000_000_000;
-    let b = (prize_x * cm.a.dy - prize_y * cm.a.dx) / (cm.a.dy * cm.b.dx - cm.a.dx * cm.b.dy);
-    let a = (prize_x - cm.b.dx * b) / cm.a.dx;
+impl Solver {
+    /// Pressing A costs 3 tokens and pressing B costs 1, with no limit on presses.
+    pub fn new() -> Self {
+        Solver {
+            prize_offset: 0,
+            press_limit: None,
+            costs: vec![3, 1],
+        }
+    }
 
-    if a >= 0
-        && b >= 0
-        && a * cm.a.dx + b * cm.b.dx == prize_x
-        && a * cm.a.dy + b * cm.b.dy == prize_y
-    {
-        Some(3 * a + b)
-    } else {
-        None
+    /// Added to every coordinate of every prize.
+    pub fn with_prize_offset(mut self, prize_offset: i64) -> Self {
+        self.prize_offset = prize_offset;
+        self
+    }
+
+    /// The most times each button may be pressed.
+    pub fn with_press_limit(mut self, press_limit: i64) -> Self {
+        self.press_limit = Some(press_limit);
+        self
+    }
+
+    /// Tokens per press of each button, in the order the buttons are listed.
+    pub fn with_costs(mut self, costs: &[u32]) -> Self {
+        self.costs = costs.to_vec();
+        self
+    }
+
+    /// The cheapest presses that win the prize, or `None` if it cannot be won.
+    pub fn cheapest(&self, claw_machine: &ClawMachine) -> anyhow::Result<Option<Presses>> {
+        let ClawMachine { buttons, prize } = claw_machine;
+        if let Some(button) = buttons.iter().find(|b| b.deltas.len() != prize.len()) {
+            return Err(anyhow!(
+                "Button {} moves in {} dimensions but the prize is in {}",
+                button.label,
+                button.deltas.len(),
+                prize.len()
+            ));
+        }
+        if let Some(button) = buttons.get(self.costs.len()) {
+            return Err(anyhow!("No cost for button {}", button.label));
+        }
+
+        let columns = buttons
+            .iter()
+            .map(|button| button.deltas.iter().copied().map(i128::from).collect())
+            .collect::<Vec<Vec<i128>>>();
+        let target = prize
+            .iter()
+            .map(|coordinate| i128::from(*coordinate) + i128::from(self.prize_offset))
+            .collect();
+        // Buttons that don't move the claw are never worth pressing.
+        let moving = (0..buttons.len())
+            .filter(|button| columns[*button].iter().any(|delta| *delta != 0))
+            .collect::<Vec<_>>();
+        let moving_columns = moving
+            .iter()
+            .map(|button| columns[*button].as_slice())
+            .collect::<Vec<_>>();
+
+        let machine = Machine {
+            solver: self,
+            rows: independent_rows(&moving_columns, prize.len())?,
+            columns: columns.clone(),
+            target,
+        };
+        let mut counts = vec![0; buttons.len()];
+        let Some(relaxation) = machine.relax(&moving, &counts)? else {
+            return Ok(None);
+        };
+
+        let radius = mul(
+            moving.len() as i128,
+            largest_subdeterminant(&moving_columns, prize.len())?,
+        )?;
+        let mut bounds = vec![(0, 0); buttons.len()];
+        for button in &moving {
+            let low = relaxation.presses[*button].div_euclid(relaxation.denominator) - radius;
+            let high = relaxation.rounded_up(*button) + radius;
+            bounds[*button] = (low.max(0), high.min(self.limit()));
+        }
+
+        let enumerated = moving
+            .iter()
+            .copied()
+            .filter(|button| !relaxation.basis.contains(button))
+            .collect::<Vec<_>>();
+        let search = Search {
+            unfixed: (0..=enumerated.len())
+                .map(|depth| [&enumerated[depth..], &relaxation.basis].concat())
+                .collect(),
+            enumerated,
+            bounds,
+            machine,
+        };
+        let mut best = None;
+        search.branch(0, &mut counts, &relaxation, &mut best)?;
+
+        Ok(best)
+    }
+
+    fn cost(&self, button: usize) -> i128 {
+        i128::from(self.costs[button])
+    }
+
+    fn limit(&self) -> i128 {
+        self.press_limit.map_or(i128::MAX, i128::from)
     }
 }
 
-#[derive(Debug)]
-struct ClawMachine {
-    a: Button,
-    b: Button,
-    prize: Prize,
+struct Machine<'a> {
+    solver: &'a Solver,
+    columns: Vec<Vec<i128>>,
+    target: Vec<i128>,
+    /// Rows of the button deltas that all the other rows depend on.
+    rows: Vec<usize>,
 }
 
-#[derive(Debug)]
-struct Button {
-    dx: i64,
-    dy: i64,
+/// A fractional solution, with presses and cost as numerators over a common denominator.
+struct Relaxation {
+    basis: Vec<usize>,
+    presses: Vec<i128>,
+    cost: i128,
+    denominator: i128,
 }
 
-#[derive(Debug)]
-struct Prize {
-    x: i64,
-    y: i64,
+impl Relaxation {
+    fn rounded_up(&self, button: usize) -> i128 {
+        -(-self.presses[button]).div_euclid(self.denominator)
+    }
+
+    /// The fewest tokens any whole number of presses near this solution can cost.
+    fn lower_bound(&self) -> i128 {
+        -(-self.cost).div_euclid(self.denominator)
+    }
 }
 
-fn parse(input: &str) -> IResult<&str, Vec<ClawMachine>> {
-    separated_list1(line_ending, claw_machine)(input)
+impl Machine<'_> {
+    /// The cheapest way to win with fractional presses of the `unfixed` buttons and the given
+    /// presses of the rest, if there is one. Some vertex of the feasible region is cheapest, and
+    /// every vertex presses all but `rank` buttons either not at all or up to the limit.
+    fn relax(&self, unfixed: &[usize], counts: &[i128]) -> anyhow::Result<Option<Relaxation>> {
+        let mut cheapest: Option<Relaxation> = None;
+        for basis in unfixed.iter().copied().combinations(self.rows.len()) {
+            let others = unfixed
+                .iter()
+                .copied()
+                .filter(|button| !basis.contains(button))
+                .collect::<Vec<_>>();
+            let num_choices = if self.solver.press_limit.is_some() {
+                1 << others.len()
+            } else {
+                1
+            };
+
+            for choice in 0..num_choices {
+                let mut counts = counts.to_vec();
+                for (position, button) in others.iter().enumerate() {
+                    if (choice >> position) & 1 == 1 {
+                        counts[*button] = self.solver.limit();
+                    }
+                }
+                let Some(vertex) = self.vertex(basis.clone(), counts)? else {
+                    continue;
+                };
+                if match &cheapest {
+                    Some(cheapest) => {
+                        mul(vertex.cost, cheapest.denominator)?
+                            < mul(cheapest.cost, vertex.denominator)?
+                    }
+                    None => true,
+                } {
+                    cheapest = Some(vertex);
+                }
+            }
+        }
+
+        Ok(cheapest)
+    }
+
+    /// Solves for the presses of the `basis` buttons given the presses of the rest, if that
+    /// presses every button between zero and the limit.
+    fn vertex(&self, basis: Vec<usize>, counts: Vec<i128>) -> anyhow::Result<Option<Relaxation>> {
+        let mut remaining = self.target.clone();
+        for (column, count) in self.columns.iter().zip(&counts) {
+            for (coordinate, delta) in remaining.iter_mut().zip(column) {
+                *coordinate = sub(*coordinate, mul(*delta, *count)?)?;
+            }
+        }
+
+        let matrix = |replaced: Option<usize>| {
+            self.rows
+                .iter()
+                .map(|row| {
+                    basis
+                        .iter()
+                        .enumerate()
+                        .map(|(position, button)| match replaced {
+                            Some(replaced) if replaced == position => remaining[*row],
+                            _ => self.columns[*button][*row],
+                        })
+                        .collect()
+                })
+                .collect::<Vec<Vec<i128>>>()
+        };
+        let basis_determinant = determinant(&matrix(None))?;
+        if basis_determinant == 0 {
+            return Ok(None);
+        }
+
+        let denominator = basis_determinant.abs();
+        let mut presses = counts
+            .iter()
+            .map(|count| mul(*count, denominator))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (position, button) in basis.iter().enumerate() {
+            presses[*button] = basis_determinant.signum() * determinant(&matrix(Some(position)))?;
+        }
+        let most = self.solver.limit().saturating_mul(denominator);
+        if presses
+            .iter()
+            .any(|numerator| *numerator < 0 || *numerator > most)
+        {
+            return Ok(None);
+        }
+
+        let mut cost = 0;
+        for (row, coordinate) in self.target.iter().enumerate() {
+            let mut reached = 0;
+            for (column, numerator) in self.columns.iter().zip(&presses) {
+                reached = add(reached, mul(column[row], *numerator)?)?;
+            }
+            if reached != mul(*coordinate, denominator)? {
+                return Ok(None);
+            }
+        }
+        for (button, numerator) in presses.iter().enumerate() {
+            cost = add(cost, mul(self.solver.cost(button), *numerator)?)?;
+        }
+
+        Ok(Some(Relaxation {
+            basis,
+            presses,
+            cost,
+            denominator,
+        }))
+    }
 }
 
-fn claw_machine(input: &str) -> IResult<&str, ClawMachine> {
-    map(
-        tuple((
-            terminated(button_a, line_ending),
-            terminated(button_b, line_ending),
-            terminated(prize, line_ending),
-        )),
-        |(button_a, button_b, prize)| ClawMachine {
-            a: button_a,
-            b: button_b,
-            prize,
-        },
-    )(input)
+/// Branch and bound over the presses of the buttons outside the cheapest fractional basis.
+struct Search<'a> {
+    machine: Machine<'a>,
+    enumerated: Vec<usize>,
+    /// The buttons whose presses are not yet fixed at each depth.
+    unfixed: Vec<Vec<usize>>,
+    bounds: Vec<(i128, i128)>,
+}
+
+impl Search<'_> {
+    /// Tries presses of the next button outwards from its fractional optimum. The fractional
+    /// cost only grows from there, so each direction stops once it cannot beat `best`.
+    fn branch(
+        &self,
+        depth: usize,
+        counts: &mut Vec<i128>,
+        relaxation: &Relaxation,
+        best: &mut Option<Presses>,
+    ) -> anyhow::Result<()> {
+        let Some(button) = self.enumerated.get(depth).copied() else {
+            let Relaxation {
+                presses,
+                cost,
+                denominator,
+                ..
+            } = relaxation;
+            if presses.iter().all(|numerator| numerator % denominator == 0)
+                && best
+                    .as_ref()
+                    .is_none_or(|best| cost / denominator < best.cost)
+            {
+                *best = Some(Presses {
+                    counts: presses
+                        .iter()
+                        .map(|numerator| numerator / denominator)
+                        .collect(),
+                    cost: cost / denominator,
+                });
+            }
+            return Ok(());
+        };
+
+        let (low, high) = self.bounds[button];
+        let start = relaxation.rounded_up(button).clamp(low, high + 1);
+        for (first, step) in [(start, 1), (start - 1, -1)] {
+            let mut presses = first;
+            while (low..=high).contains(&presses) {
+                counts[button] = presses;
+                let Some(relaxation) = self.machine.relax(&self.unfixed[depth + 1], counts)? else {
+                    break;
+                };
+                if best
+                    .as_ref()
+                    .is_some_and(|best| relaxation.lower_bound() >= best.cost)
+                {
+                    break;
+                }
+                self.branch(depth + 1, counts, &relaxation, best)?;
+                presses += step;
+            }
+        }
+        counts[button] = 0;
+
+        Ok(())
+    }
+}
+
+/// Some cheapest solution presses each button within `n * Δ` of a cheapest fractional one, for
+/// `n` buttons whose deltas have largest subdeterminant `Δ` (Cook et al., 1986).
+fn largest_subdeterminant(columns: &[&[i128]], num_rows: usize) -> anyhow::Result<i128> {
+    let mut largest = 1;
+    for size in 1..=num_rows.min(columns.len()) {
+        for rows in (0..num_rows).combinations(size) {
+            for chosen in columns.iter().combinations(size) {
+                largest = largest.max(determinant(&minor(&chosen, &rows))?.abs());
+            }
+        }
+    }
+
+    Ok(largest)
+}
+
+/// As many rows as the rank of the columns, chosen so the other rows depend on them.
+fn independent_rows(columns: &[&[i128]], num_rows: usize) -> anyhow::Result<Vec<usize>> {
+    for size in (1..=num_rows.min(columns.len())).rev() {
+        for rows in (0..num_rows).combinations(size) {
+            for chosen in columns.iter().combinations(size) {
+                if determinant(&minor(&chosen, &rows))? != 0 {
+                    return Ok(rows);
+                }
+            }
+        }
+    }
+
+    Ok(vec![])
+}
+
+fn minor(columns: &[&&[i128]], rows: &[usize]) -> Vec<Vec<i128>> {
+    rows.iter()
+        .map(|row| columns.iter().map(|column| column[*row]).collect())
+        .collect()
 }
 
-fn button_a(input: &str) -> IResult<&str, Button> {
-    map(preceded(tag("Button A: "), deltas), |(dx, dy)| Button {
-        dx,
-        dy,
-    })(input)
+fn determinant(matrix: &[Vec<i128>]) -> anyhow::Result<i128> {
+    let Some((first_row, rest)) = matrix.split_first() else {
+        return Ok(1);
+    };
+
+    let mut total = 0;
+    for (column, entry) in first_row.iter().enumerate() {
+        if *entry == 0 {
+            continue;
+        }
+        let minor = rest
+            .iter()
+            .map(|row| {
+                let mut row = row.clone();
+                row.remove(column);
+                row
+            })
+            .collect::<Vec<_>>();
+        let term = mul(*entry, determinant(&minor)?)?;
+        total = if column % 2 == 0 {
+            add(total, term)?
+        } else {
+            sub(total, term)?
+        };
+    }
+
+    Ok(total)
 }
 
-fn button_b(input: &str) -> IResult<&str, Button> {
-    map(preceded(tag("Button B: "), deltas), |(dx, dy)| Button {
-        dx,
-        dy,
-    })(input)
+fn add(a: i128, b: i128) -> anyhow::Result<i128> {
+    a.checked_add(b)
+        .ok_or_else(|| anyhow!("{a} + {b} overflows"))
 }
 
-fn deltas(input: &str) -> IResult<&str, (i64, i64)> {
-    separated_pair(
-        preceded(tag("X+"), character::complete::i64),
-        tag(", "),
-        preceded(tag("Y+"), character::complete::i64),
+fn sub(a: i128, b: i128) -> anyhow::Result<i128> {
+    a.checked_sub(b)
+        .ok_or_else(|| anyhow!("{a} - {b} overflows"))
+}
+
+fn mul(a: i128, b: i128) -> anyhow::Result<i128> {
+    a.checked_mul(b)
+        .ok_or_else(|| anyhow!("{a} * {b} overflows"))
+}
+
+/// A claw machine with any number of buttons, moving in two or three dimensions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClawMachine {
+    pub buttons: Vec<Button>,
+    pub prize: Vec<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Button {
+    pub label: char,
+    /// How far one press moves the claw along each of X, Y and, optionally, Z.
+    pub deltas: Vec<i64>,
+}
+
+pub fn parse(input: &str) -> anyhow::Result<Vec<ClawMachine>> {
+    let (_, claw_machines) = all_consuming(terminated(
+        separated_list1(line_ending, claw_machine),
+        multispace0,
+    ))(input)
+    .map_err(|e| anyhow!("Unable to parse input: {e}"))?;
+    Ok(claw_machines)
+}
+
+fn claw_machine(input: &str) -> IResult<&str, ClawMachine> {
+    map(
+        pair(
+            many1(terminated(button, line_ending)),
+            terminated(prize, opt(line_ending)),
+        ),
+        |(buttons, prize)| ClawMachine { buttons, prize },
     )(input)
 }
 
-fn prize(input: &str) -> IResult<&str, Prize> {
+fn button(input: &str) -> IResult<&str, Button> {
     map(
-        separated_pair(
-            preceded(tag("Prize: X="), character::complete::i64),
-            tag(", Y="),
-            character::complete::i64,
+        pair(
+            delimited(tag("Button "), anychar, tag(": ")),
+            coordinates(""),
         ),
-        |(x, y)| Prize { x, y },
+        |(label, deltas)| Button { label, deltas },
     )(input)
 }
 
+fn prize(input: &str) -> IResult<&str, Vec<i64>> {
+    preceded(tag("Prize: "), coordinates("="))(input)
+}
+
+/// Comma separated values for the X, Y and optionally Z axes, in that order.
+fn coordinates<'a>(separator: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<i64>> {
+    map(
+        verify(
+            separated_list1(
+                tag(", "),
+                tuple((one_of("XYZ"), tag(separator), character::complete::i64)),
+            ),
+            |axes: &Vec<(char, &str, i64)>| {
+                (2..=3).contains(&axes.len())
+                    && axes
+                        .iter()
+                        .zip("XYZ".chars())
+                        .all(|((axis, _, _), expected)| *axis == expected)
+            },
+        ),
+        |axes| axes.into_iter().map(|(_, _, value)| value).collect(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
-    use super::part1;
+    use super::{fewest_tokens, parse, part1, part2, Button, ClawMachine, Presses, Solver};
+    use itertools::Itertools;
+    use proptest::prelude::*;
 
     const INPUT: &str = "Button A: X+94, Y+34
 Button B: X+22, Y+67
@@ -150,4 +547,208 @@ Prize: X=18641, Y=10279
         assert_eq!(part1(INPUT)?, 480);
         Ok(())
     }
+
+    #[test]
+    pub fn part2_returns_minimum_number_of_tokens_for_distant_prizes() -> anyhow::Result<()> {
+        assert_eq!(part2(INPUT)?, 875318608908);
+        Ok(())
+    }
+
+    #[test]
+    pub fn collinear_buttons_use_cheapest_combination() -> anyhow::Result<()> {
+        let machine =
+            |x, y| format!("Button A: X+2, Y+2\nButton B: X+4, Y+4\nPrize: X={x}, Y={y}\n");
+
+        // a + 2b = 5 is cheapest at a = 1, b = 2 when B is cheap and at a = 5 when A is.
+        assert_eq!(fewest_tokens(&machine(10, 10), &Solver::new())?, 5);
+        assert_eq!(
+            fewest_tokens(&machine(10, 10), &Solver::new().with_costs(&[1, 3]))?,
+            5
+        );
+        assert_eq!(
+            fewest_tokens(&machine(10, 10), &Solver::new().with_costs(&[4, 1]))?,
+            6
+        );
+        assert_eq!(
+            fewest_tokens(&machine(10, 10), &Solver::new().with_press_limit(1))?,
+            0
+        );
+        assert_eq!(fewest_tokens(&machine(10, 11), &Solver::new())?, 0);
+        assert_eq!(fewest_tokens(&machine(5, 5), &Solver::new())?, 0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn machines_with_more_buttons_or_dimensions() -> anyhow::Result<()> {
+        let machines = parse(
+            "Button A: X+1, Y+0
+Button B: X+0, Y+1
+Button C: X+1, Y+1
+Prize: X=5, Y=3
+
+Button A: X+1, Y+0, Z+0
+Button B: X+0, Y+1, Z+0
+Button C: X+0, Y+0, Z+2
+Button D: X+1, Y+1, Z+2
+Prize: X=2, Y=3, Z=4
+
+Button A: X+1, Y+0
+Button B: X+0, Y+1
+Button C: X+1, Y+1
+Button D: X+2, Y+1
+Prize: X=4, Y=2",
+        )?;
+        let cheapest = |costs: &[u32], machine| Solver::new().with_costs(costs).cheapest(machine);
+
+        assert_eq!(
+            cheapest(&[3, 1, 1], &machines[0])?,
+            Some(Presses {
+                counts: vec![2, 0, 3],
+                cost: 9
+            })
+        );
+        assert_eq!(
+            cheapest(&[3, 1, 1, 1], &machines[1])?,
+            Some(Presses {
+                counts: vec![0, 1, 0, 2],
+                cost: 3
+            })
+        );
+        assert_eq!(
+            cheapest(&[1, 1, 1, 1], &machines[2])?,
+            Some(Presses {
+                counts: vec![0, 0, 0, 2],
+                cost: 2
+            })
+        );
+        assert!(cheapest(&[3, 1], &machines[0]).is_err());
+        assert!(parse("Button A: X+1, Y+2\nPrize: X=1, Z=2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    pub fn dependent_buttons_scale_to_distant_prizes() -> anyhow::Result<()> {
+        let machines = parse(
+            "Button A: X+94, Y+34
+Button B: X+22, Y+67
+Button C: X+17, Y+86
+Button D: X+84, Y+37
+Prize: X=8400, Y=5400
+
+Button A: X+1, Y+0, Z+0
+Button B: X+0, Y+1, Z+0
+Button C: X+0, Y+0, Z+1
+Button D: X+1, Y+1, Z+1
+Button E: X+1, Y+1, Z+0
+Prize: X=3, Y=1, Z=0
+
+",
+        )?;
+        let cost = |costs: &[u32], offset, machine| {
+            Solver::new()
+                .with_costs(costs)
+                .with_prize_offset(offset)
+                .cheapest(machine)
+                .map(|presses| presses.map(|presses| presses.cost))
+        };
+
+        assert_eq!(
+            cost(&[3, 1, 2, 1], 10_000_000, &machines[0])?,
+            Some(191_241)
+        );
+        assert!(cost(&[3, 1, 2, 1], 10_000_000_000_000, &machines[0])?.is_some());
+        // D covers Z more cheaply than C, then E covers what it can of X and Y.
+        assert_eq!(
+            cost(&[3, 3, 3, 2, 1], 10_000_000_000_000, &machines[1])?,
+            Some(20_000_000_000_007)
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn buttons_that_do_not_move_are_never_pressed() -> anyhow::Result<()> {
+        let machines = parse(
+            "Button A: X+1, Y+0
+Button B: X+0, Y+0
+Button C: X+0, Y+0
+Button D: X+0, Y+1
+Prize: X=5, Y=5",
+        )?;
+
+        assert_eq!(
+            Solver::new()
+                .with_costs(&[3, 1, 1, 1])
+                .cheapest(&machines[0])?,
+            Some(Presses {
+                counts: vec![5, 0, 0, 5],
+                cost: 20
+            })
+        );
+        Ok(())
+    }
+
+    fn brute_force(claw_machine: &ClawMachine, costs: &[u32], limit: i64) -> Option<i128> {
+        claw_machine
+            .buttons
+            .iter()
+            .map(|_| 0..=limit)
+            .multi_cartesian_product()
+            .filter(|counts| {
+                claw_machine
+                    .prize
+                    .iter()
+                    .enumerate()
+                    .all(|(axis, coordinate)| {
+                        claw_machine
+                            .buttons
+                            .iter()
+                            .zip(counts)
+                            .map(|(button, count)| button.deltas[axis] * count)
+                            .sum::<i64>()
+                            == *coordinate
+                    })
+            })
+            .map(|counts| {
+                counts
+                    .iter()
+                    .zip(costs)
+                    .map(|(count, cost)| i128::from(*count) * i128::from(*cost))
+                    .sum()
+            })
+            .min()
+    }
+
+    fn small_machine() -> impl Strategy<Value = (ClawMachine, Vec<u32>)> {
+        (2..=3usize, 1..=4usize).prop_flat_map(|(dimensions, num_buttons)| {
+            (
+                prop::collection::vec(prop::collection::vec(-2..=3i64, dimensions), num_buttons),
+                prop::collection::vec(0..=12i64, dimensions),
+                prop::collection::vec(0..=4u32, num_buttons),
+            )
+                .prop_map(|(deltas, prize, costs)| {
+                    let buttons = deltas
+                        .into_iter()
+                        .zip('A'..)
+                        .map(|(deltas, label)| Button { label, deltas })
+                        .collect();
+                    (ClawMachine { buttons, prize }, costs)
+                })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn cheapest_matches_brute_force((machine, costs) in small_machine()) {
+            let presses = Solver::new()
+                .with_costs(&costs)
+                .with_press_limit(5)
+                .cheapest(&machine)
+                .unwrap();
+
+            prop_assert_eq!(
+                presses.map(|presses| presses.cost),
+                brute_force(&machine, &costs, 5)
+            );
+        }
+    }
 }
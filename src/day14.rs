@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
 use anyhow::anyhow;
+use ndarray::Array2;
 use nom::{
     bytes::complete::tag,
     character::{self, complete::line_ending},
@@ -12,116 +13,358 @@ use nom::{
 
 pub const MAP_DIMS: (usize, usize) = (101, 103);
 
-pub fn part1(input: &str, dims: (usize, usize)) -> anyhow::Result<i32> {
-    let (_, robots) = parse(input).map_err(|e| anyhow!("Unable to parse input: {e}"))?;
+pub fn part1(input: &str, dims: (usize, usize)) -> anyhow::Result<usize> {
+    let swarm = RobotSwarm::parse(input, dims)?;
+    Ok(swarm.region_counts(100, 2)?.iter().product())
+}
 
-    let quadrant_counts = robots
-        .iter()
-        .filter_map(|robot| identify_quadrant(&simulate(robot, 100, dims), &dims))
-        .fold(HashMap::<Quadrant, i32>::default(), |mut acc, quadrant| {
-            *acc.entry(quadrant).or_insert(0) += 1;
-            acc
-        });
+pub fn part2(input: &str, dims: (usize, usize)) -> anyhow::Result<i64> {
+    let candidates = RobotSwarm::parse(input, dims)?.easter_egg_candidates(3)?;
+    candidates
+        .first()
+        .map(|candidate| candidate.second)
+        .ok_or(anyhow!("Robots never line up in both directions at once"))
+}
+
+/// What happens to a robot that reaches the edge of the map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Boundary {
+    /// Teleports to the opposite edge.
+    #[default]
+    Wrap,
+    /// Reflects off the edge, reversing direction.
+    Bounce,
+    /// Stops at the edge.
+    Clamp,
+}
+
+impl Boundary {
+    /// The position along an axis of `size` tiles after moving for `seconds`.
+    fn advance(&self, start: i64, velocity: i64, seconds: i64, size: i64) -> i64 {
+        let unbounded = i128::from(start) + i128::from(velocity) * i128::from(seconds);
+        let size = i128::from(size);
+        let position = match self {
+            Boundary::Wrap => unbounded.rem_euclid(size),
+            Boundary::Bounce if size == 1 => 0,
+            Boundary::Bounce => {
+                let unfolded = unbounded.rem_euclid(2 * (size - 1));
+                if unfolded < size {
+                    unfolded
+                } else {
+                    2 * (size - 1) - unfolded
+                }
+            }
+            Boundary::Clamp => unbounded.clamp(0, size - 1),
+        };
+
+        position as i64
+    }
+
+    /// How often a robot's position and direction along an axis repeat, if they do.
+    fn period(&self, velocity: i64, size: i64) -> Option<i64> {
+        let cycle = match self {
+            Boundary::Wrap => size,
+            Boundary::Bounce => (2 * (size - 1)).max(1),
+            Boundary::Clamp => return None,
+        };
+        Some(cycle / gcd(velocity.abs(), cycle))
+    }
+}
 
-    Ok(quadrant_counts.values().product())
+/// A swarm of robots moving in straight lines on a map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RobotSwarm {
+    robots: Vec<Robot>,
+    width: i64,
+    height: i64,
+    boundary: Boundary,
 }
 
-pub fn part2(input: &str, dims: (usize, usize)) -> anyhow::Result<i32> {
-    let (_, robots) = parse(input).map_err(|e| anyhow!("Unable to parse input: {e}"))?;
+impl RobotSwarm {
+    pub fn parse(input: &str, (width, height): (usize, usize)) -> anyhow::Result<Self> {
+        let (_, robots) = parse(input).map_err(|e| anyhow!("Unable to parse input: {e}"))?;
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Map must be at least one tile wide and high"));
+        }
+
+        Ok(RobotSwarm {
+            robots,
+            width: i64::try_from(width)?,
+            height: i64::try_from(height)?,
+            boundary: Boundary::default(),
+        })
+    }
+
+    pub fn with_boundary(mut self, boundary: Boundary) -> Self {
+        self.boundary = boundary;
+        self
+    }
 
-    let mut seconds = 0;
-    loop {
-        seconds += 1;
-        let positions = robots
+    pub fn positions_at(&self, seconds: i64) -> Vec<Position> {
+        self.robots
             .iter()
-            .map(|robot| simulate(robot, seconds, dims))
-            .collect::<Vec<Position>>();
-        let unique_positions = positions.iter().cloned().collect::<HashSet<Position>>();
+            .map(|robot| Position {
+                x: self
+                    .boundary
+                    .advance(robot.position.x, robot.velocity.x, seconds, self.width),
+                y: self
+                    .boundary
+                    .advance(robot.position.y, robot.velocity.y, seconds, self.height),
+            })
+            .collect()
+    }
+
+    /// Counts the robots in each of `k` by `k` equal sub-regions, indexed by `[row, col]`.
+    ///
+    /// Robots on tiles that straddle a dividing line, like the middle row and column of a map
+    /// with odd dimensions split in two, are not counted.
+    pub fn region_counts(&self, seconds: i64, k: usize) -> anyhow::Result<Array2<usize>> {
+        if k == 0 {
+            return Err(anyhow!("Map must be split into at least one region"));
+        }
+
+        let mut counts = Array2::zeros((k, k));
+        let k = i64::try_from(k)?;
+        for position in self.positions_at(seconds) {
+            if let (Some(row), Some(col)) = (
+                sub_region(position.y, self.height, k),
+                sub_region(position.x, self.width, k),
+            ) {
+                counts[(row, col)] += 1;
+            }
+        }
 
-        if positions.len() == unique_positions.len() {
-            return Ok(seconds);
+        Ok(counts)
+    }
+
+    /// The first second after which the swarm returns to a configuration it was already in.
+    ///
+    /// Wrapping and bouncing robots return to where they started, moving the same way, once a
+    /// whole number of their periods have passed. Clamped robots never return but eventually
+    /// all come to rest, after which every second repeats the one before.
+    pub fn first_repeat(&self) -> anyhow::Result<i64> {
+        if self.boundary == Boundary::Clamp {
+            let settled = self
+                .robots
+                .iter()
+                .flat_map(|robot| {
+                    [
+                        settling_time(robot.position.x, robot.velocity.x, self.width),
+                        settling_time(robot.position.y, robot.velocity.y, self.height),
+                    ]
+                })
+                .max()
+                .unwrap_or(0);
+            return Ok(settled + 1);
         }
+
+        self.robots
+            .iter()
+            .flat_map(|robot| {
+                [
+                    self.boundary.period(robot.velocity.x, self.width),
+                    self.boundary.period(robot.velocity.y, self.height),
+                ]
+            })
+            .flatten()
+            .try_fold(1i64, |period, robot_period| {
+                (period / gcd(period, robot_period)).checked_mul(robot_period)
+            })
+            .ok_or(anyhow!("Swarm period overflows"))
+    }
+
+    /// Ranks the seconds at which the robots are most likely to form a picture, best first.
+    ///
+    /// X positions repeat every x period and y positions every y period, so the seconds whose
+    /// x and y positions are least spread out are found separately and combined with the
+    /// Chinese remainder theorem. Every pairing of the `count` best x and y offsets is then
+    /// ranked by its largest cluster and, among equally large clusters, by lowest entropy.
+    pub fn easter_egg_candidates(&self, count: usize) -> anyhow::Result<Vec<Candidate>> {
+        let (Some(x_period), Some(y_period)) = (
+            self.boundary.period(1, self.width),
+            self.boundary.period(1, self.height),
+        ) else {
+            return Err(anyhow!("Clamped robots do not move periodically"));
+        };
+
+        let x_offsets = best_offsets(x_period, count, |seconds| {
+            variance(self.positions_at(seconds).iter().map(|position| position.x))
+        });
+        let y_offsets = best_offsets(y_period, count, |seconds| {
+            variance(self.positions_at(seconds).iter().map(|position| position.y))
+        });
+
+        let mut candidates = x_offsets
+            .iter()
+            .flat_map(|(x_offset, x_variance)| {
+                y_offsets.iter().filter_map(|(y_offset, y_variance)| {
+                    let second = chinese_remainder(*x_offset, x_period, *y_offset, y_period)?;
+                    let positions = self.positions_at(second);
+                    Some(Candidate {
+                        second,
+                        x_variance: *x_variance,
+                        y_variance: *y_variance,
+                        entropy: entropy(&positions, self.width),
+                        largest_cluster: largest_cluster(&positions),
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|a, b| {
+            b.largest_cluster
+                .cmp(&a.largest_cluster)
+                .then(a.entropy.total_cmp(&b.entropy))
+                .then(a.second.cmp(&b.second))
+        });
+        candidates.dedup_by_key(|candidate| candidate.second);
+        candidates.truncate(count);
+
+        Ok(candidates)
     }
 }
 
-fn simulate(robot: &Robot, seconds: i32, dims: (usize, usize)) -> Position {
-    justify(robot.position + robot.velocity.displacement(seconds), dims)
+/// Which of `k` equal bands along an axis of `size` tiles a tile falls in, unless a dividing
+/// line passes through it.
+fn sub_region(position: i64, size: i64, k: i64) -> Option<usize> {
+    let band = position * k / size;
+    (k * (position + 1) <= (band + 1) * size).then_some(band as usize)
 }
 
-fn justify(new_position: Position, dims: (usize, usize)) -> Position {
-    Position {
-        x: new_position.x.rem_euclid(dims.0 as i32),
-        y: new_position.y.rem_euclid(dims.1 as i32),
+/// How long a clamped robot keeps moving along an axis before it reaches the edge.
+fn settling_time(start: i64, velocity: i64, size: i64) -> i64 {
+    match velocity.signum() {
+        1 => ((size - 1 - start).max(0) + velocity - 1) / velocity,
+        -1 => (start.max(0) - velocity - 1) / -velocity,
+        _ => 0,
     }
 }
 
-fn identify_quadrant(position: &Position, dims: &(usize, usize)) -> Option<Quadrant> {
-    let width = dims.0 as i32;
-    let height = dims.1 as i32;
-    let left = position.x < width / 2;
-    let right = position.x > (width / 2);
-    let top = position.y < height / 2;
-    let bottom = position.y > (height / 2);
-    if left && top {
-        Some(Quadrant::Northwest)
-    } else if right && top {
-        Some(Quadrant::Northeast)
-    } else if left && bottom {
-        Some(Quadrant::Southwest)
-    } else if right && bottom {
-        Some(Quadrant::Southeast)
-    } else {
-        None
-    }
+/// A second at which the robots might form a picture, with the measures used to rank it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub second: i64,
+    pub x_variance: f64,
+    pub y_variance: f64,
+    /// Shannon entropy, in bits, of how the robots are spread over blocks of the map.
+    pub entropy: f64,
+    /// The most tiles in a group of occupied tiles connected horizontally or vertically.
+    pub largest_cluster: usize,
 }
 
-#[derive(PartialEq, Eq, Hash)]
-enum Quadrant {
-    Northwest,
-    Northeast,
-    Southwest,
-    Southeast,
+/// The `count` seconds within one period with the lowest scores, paired with their scores.
+fn best_offsets(period: i64, count: usize, score: impl Fn(i64) -> f64) -> Vec<(i64, f64)> {
+    let mut offsets = (0..period)
+        .map(|seconds| (seconds, score(seconds)))
+        .collect::<Vec<_>>();
+    offsets.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    offsets.truncate(count);
+    offsets
 }
 
-struct Robot {
-    position: Position,
-    velocity: Velocity,
+fn variance(values: impl Iterator<Item = i64>) -> f64 {
+    let (count, sum, sum_of_squares) =
+        values.fold((0.0, 0.0, 0.0), |(count, sum, squares), value| {
+            let value = value as f64;
+            (count + 1.0, sum + value, squares + value * value)
+        });
+    if count == 0.0 {
+        return 0.0;
+    }
+
+    let mean = sum / count;
+    sum_of_squares / count - mean * mean
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-struct Position {
-    x: i32,
-    y: i32,
+const ENTROPY_BLOCK_SIZE: i64 = 5;
+
+fn entropy(positions: &[Position], width: i64) -> f64 {
+    let blocks_per_row = (width + ENTROPY_BLOCK_SIZE - 1) / ENTROPY_BLOCK_SIZE;
+    let mut blocks = HashMap::<i64, usize>::new();
+    for position in positions {
+        let block =
+            (position.y / ENTROPY_BLOCK_SIZE) * blocks_per_row + position.x / ENTROPY_BLOCK_SIZE;
+        *blocks.entry(block).or_default() += 1;
+    }
+
+    let total = positions.len() as f64;
+    blocks
+        .values()
+        .map(|count| {
+            let probability = *count as f64 / total;
+            -probability * probability.log2()
+        })
+        .sum()
 }
 
-impl std::ops::Add<Displacement> for Position {
-    type Output = Position;
+fn largest_cluster(positions: &[Position]) -> usize {
+    let mut occupied = positions
+        .iter()
+        .map(|position| (position.x, position.y))
+        .collect::<HashSet<_>>();
+    let mut largest = 0;
 
-    fn add(self, rhs: Displacement) -> Self::Output {
-        Position {
-            x: self.x + rhs.x,
-            y: self.y + rhs.y,
+    while let Some(start) = occupied.iter().next().copied() {
+        occupied.remove(&start);
+        let mut stack = vec![start];
+        let mut size = 0;
+        while let Some((x, y)) = stack.pop() {
+            size += 1;
+            for neighbor in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if occupied.remove(&neighbor) {
+                    stack.push(neighbor);
+                }
+            }
         }
+        largest = largest.max(size);
     }
+
+    largest
 }
 
-struct Velocity {
-    x: i32,
-    y: i32,
+/// The second within `lcm(x_period, y_period)` that is `x_offset` into the x period and
+/// `y_offset` into the y period, if there is one.
+fn chinese_remainder(x_offset: i64, x_period: i64, y_offset: i64, y_period: i64) -> Option<i64> {
+    let (gcd, inverse, _) = extended_gcd(x_period, y_period);
+    if (y_offset - x_offset) % gcd != 0 {
+        return None;
+    }
+
+    let lcm = x_period / gcd * y_period;
+    let periods = ((y_offset - x_offset) / gcd * inverse).rem_euclid(y_period / gcd);
+    Some((x_offset + x_period * periods).rem_euclid(lcm))
 }
 
-impl Velocity {
-    fn displacement(&self, seconds: i32) -> Displacement {
-        Displacement {
-            x: self.x * seconds,
-            y: self.y * seconds,
-        }
+/// Returns `(g, x, y)` with `g = gcd(a, b)` and `a * x + b * y == g`.
+fn extended_gcd(a: i64, b: i64) -> (i64, i64, i64) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (gcd, x, y) = extended_gcd(b, a % b);
+        (gcd, y, x - (a / b) * y)
     }
 }
 
-struct Displacement {
-    x: i32,
-    y: i32,
+fn gcd(a: i64, b: i64) -> i64 {
+    extended_gcd(a, b).0
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Robot {
+    position: Position,
+    velocity: Velocity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Velocity {
+    x: i64,
+    y: i64,
 }
 
 fn parse(input: &str) -> IResult<&str, Vec<Robot>> {
@@ -140,9 +383,9 @@ fn position(input: &str) -> IResult<&str, Position> {
         preceded(
             tag("p="),
             separated_pair(
-                character::complete::i32,
+                character::complete::i64,
                 character::complete::char(','),
-                character::complete::i32,
+                character::complete::i64,
             ),
         ),
         |(x, y)| Position { x, y },
@@ -154,9 +397,9 @@ fn velocity(input: &str) -> IResult<&str, Velocity> {
         preceded(
             tag("v="),
             separated_pair(
-                character::complete::i32,
+                character::complete::i64,
                 character::complete::char(','),
-                character::complete::i32,
+                character::complete::i64,
             ),
         ),
         |(x, y)| Velocity { x, y },
@@ -165,7 +408,8 @@ fn velocity(input: &str) -> IResult<&str, Velocity> {
 
 #[cfg(test)]
 mod tests {
-    use super::part1;
+    use super::{chinese_remainder, part1, part2, Boundary, Position, RobotSwarm, MAP_DIMS};
+    use ndarray::array;
 
     const INPUT: &str = "p=0,4 v=3,-3
 p=6,3 v=-1,-3
@@ -185,4 +429,91 @@ p=9,5 v=-3,-3";
         assert_eq!(part1(INPUT, (11, 7))?, 12);
         Ok(())
     }
+
+    /// Robots that form a filled square at `second`, among robots scattered at random.
+    fn picture_at(second: i64) -> String {
+        let (width, height) = (MAP_DIMS.0 as i64, MAP_DIMS.1 as i64);
+        let mut seed = 17u64;
+        let mut random = |bound: i64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) % bound as u64) as i64
+        };
+
+        let mut robots = vec![];
+        for index in 0..250 {
+            let (vx, vy) = (random(201) - 100, random(201) - 100);
+            let (x, y) = if index < 144 {
+                (40 + index % 12, 50 + index / 12)
+            } else {
+                (random(width), random(height))
+            };
+            let start_x = (x - vx * second).rem_euclid(width);
+            let start_y = (y - vy * second).rem_euclid(height);
+            robots.push(format!("p={start_x},{start_y} v={vx},{vy}"));
+        }
+
+        robots.join("\n")
+    }
+
+    #[test]
+    pub fn part2_finds_picture_within_period() -> anyhow::Result<()> {
+        let input = picture_at(7_345);
+        assert_eq!(part2(&input, MAP_DIMS)?, 7_345);
+
+        let candidates = RobotSwarm::parse(&input, MAP_DIMS)?.easter_egg_candidates(3)?;
+        assert_eq!(candidates.len(), 3);
+        assert!(candidates[0].largest_cluster >= 144);
+        assert!(candidates[0].entropy < candidates[1].entropy);
+        Ok(())
+    }
+
+    #[test]
+    pub fn chinese_remainder_combines_offsets() {
+        assert_eq!(chinese_remainder(2, 3, 3, 5), Some(8));
+        assert_eq!(chinese_remainder(1, 4, 3, 6), Some(9));
+        assert_eq!(chinese_remainder(1, 4, 2, 6), None);
+    }
+
+    #[test]
+    pub fn boundaries_wrap_bounce_or_clamp() -> anyhow::Result<()> {
+        let swarm = RobotSwarm::parse("p=2,4 v=2,-3", (11, 7))?;
+        let after = |boundary, seconds| swarm.clone().with_boundary(boundary).positions_at(seconds);
+
+        assert_eq!(after(Boundary::Wrap, 5), vec![Position { x: 1, y: 3 }]);
+        assert_eq!(after(Boundary::Bounce, 5), vec![Position { x: 8, y: 1 }]);
+        assert_eq!(after(Boundary::Clamp, 5), vec![Position { x: 10, y: 0 }]);
+        assert_eq!(
+            after(Boundary::Wrap, 10_i64.pow(15)),
+            after(Boundary::Wrap, 10_i64.pow(15) % 77)
+        );
+
+        assert_eq!(
+            swarm
+                .clone()
+                .with_boundary(Boundary::Bounce)
+                .first_repeat()?,
+            20
+        );
+        assert_eq!(
+            swarm
+                .clone()
+                .with_boundary(Boundary::Clamp)
+                .first_repeat()?,
+            5
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn region_counts_split_map_into_equal_parts() -> anyhow::Result<()> {
+        let swarm = RobotSwarm::parse(INPUT, (11, 7))?;
+
+        assert_eq!(swarm.region_counts(100, 2)?, array![[1, 3], [4, 1]]);
+        assert_eq!(swarm.region_counts(100, 1)?, array![[12]]);
+        assert!(swarm.region_counts(100, 0).is_err());
+        assert_eq!(swarm.first_repeat()?, 77);
+        Ok(())
+    }
 }